@@ -0,0 +1,55 @@
+// everything a signal needs to know about the stream it ends up in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioContext {
+    pub sample_rate: u32,
+}
+
+impl Default for AudioContext {
+    fn default() -> Self {
+        Self { sample_rate: 44100 }
+    }
+}
+
+impl AudioContext {
+    pub fn new(sample_rate: u32) -> Self {
+        Self { sample_rate }
+    }
+
+    // processing a file keeps its sample rate
+    pub fn from_spec(spec: &hound::WavSpec) -> Self {
+        Self::new(spec.sample_rate)
+    }
+
+    // first command line argument, e.g. `cargo run -- 48000`
+    pub fn from_args() -> Result<Self, Box<dyn std::error::Error>> {
+        match std::env::args().nth(1) {
+            Some(sample_rate) => match sample_rate.parse::<u32>() {
+                Ok(sample_rate) if sample_rate > 0 => Ok(Self::new(sample_rate)),
+                _ => Err(format!("`{}` is not a valid sample rate", sample_rate).into()),
+            },
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub fn rate(&self) -> f32 {
+        self.sample_rate as f32
+    }
+
+    pub fn nyquist(&self) -> f32 {
+        self.rate() / 2.0
+    }
+
+    pub fn samples(&self, seconds: f32) -> usize {
+        (seconds * self.rate()) as usize
+    }
+
+    // cycles per sample at `freq` Hz
+    pub fn increment(&self, freq: f32) -> f64 {
+        freq as f64 / self.sample_rate as f64
+    }
+
+    // 16 bit integer WAV at this sample rate
+    pub fn wav_spec(&self, channels: u16) -> hound::WavSpec {
+        crate::sample::spec_for::<i16>(*self, channels)
+    }
+}
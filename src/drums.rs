@@ -0,0 +1,258 @@
+use crate::context::AudioContext;
+use crate::lo_pass_filter::OnePole;
+use crate::noise::white_noise;
+use crate::oscillators::{BandLimited, Waveform};
+use crate::pythagorean_chords::{barka_timeline, make_barka, AMPLITUDE};
+use crate::sample::Sample;
+use crate::tuning::{Pythagorean, A4};
+
+const OUTPUT_FILE: &str = "./output/drums_barka.wav";
+const SILENCE: f32 = 0.001; // -60 dB, hits stop once every decay got this quiet
+const HAT_RATIOS: [f32; 6] = [1.0, 1.4828, 1.8005, 2.5464, 2.6305, 3.8971]; // 808 cluster
+
+// exponential decay with a time constant of `decay` seconds
+fn decay(time: f32, decay: f32) -> f32 {
+    (-time / decay.max(1e-4)).exp()
+}
+
+fn length(context: AudioContext, longest_decay: f32) -> usize {
+    context.samples(longest_decay * -SILENCE.ln())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Kick {
+    pub start_freq: f32,
+    pub end_freq: f32,
+    pub sweep: f32, // seconds, time constant of the pitch drop
+    pub decay: f32,
+    pub click: f32, // 0.0 - 1.0, short noise burst at the start
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Snare {
+    pub tone_freq: f32,
+    pub tone_decay: f32,
+    pub noise_decay: f32,
+    pub snappy: f32,     // 0.0 - 1.0, noise against tone
+    pub brightness: f32, // Hz, noise below this gets filtered out
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HiHat {
+    pub base_freq: f32, // lowest square of the metallic cluster
+    pub decay: f32,     // short for closed, long for open hats
+    pub brightness: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tom {
+    pub freq: f32,
+    pub decay: f32,
+    pub bend: f32, // 0.0 - 1.0, how far the pitch drops
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Drum {
+    Kick(Kick),
+    Snare(Snare),
+    HiHat(HiHat),
+    Tom(Tom),
+}
+
+impl Drum {
+    pub fn kick() -> Self {
+        Drum::Kick(Kick {
+            start_freq: 180.0,
+            end_freq: 48.0,
+            sweep: 0.04,
+            decay: 0.3,
+            click: 0.3,
+        })
+    }
+
+    pub fn snare() -> Self {
+        Drum::Snare(Snare {
+            tone_freq: 185.0,
+            tone_decay: 0.06,
+            noise_decay: 0.12,
+            snappy: 0.6,
+            brightness: 1500.0,
+        })
+    }
+
+    pub fn closed_hat() -> Self {
+        Drum::HiHat(HiHat {
+            base_freq: 205.3,
+            decay: 0.03,
+            brightness: 7000.0,
+        })
+    }
+
+    pub fn open_hat() -> Self {
+        Drum::HiHat(HiHat {
+            base_freq: 205.3,
+            decay: 0.25,
+            brightness: 7000.0,
+        })
+    }
+
+    pub fn tom(freq: f32) -> Self {
+        Drum::Tom(Tom {
+            freq,
+            decay: 0.25,
+            bend: 0.3,
+        })
+    }
+
+    pub fn hit(self, context: AudioContext, velocity: f32) -> Box<dyn Iterator<Item = f32>> {
+        let time = move |index: usize| index as f32 / context.rate();
+        let gain = velocity * AMPLITUDE;
+        match self {
+            Drum::Kick(kick) => {
+                let mut body = BandLimited::new(context, Waveform::Sine, kick.start_freq);
+                let mut noise = white_noise(1).map(|v| v / AMPLITUDE);
+                Box::new((0..length(context, kick.decay)).map(move |index| {
+                    let t = time(index);
+                    let freq =
+                        kick.end_freq + (kick.start_freq - kick.end_freq) * decay(t, kick.sweep);
+                    let click = noise.next().unwrap_or(0.0) * kick.click * decay(t, 0.002);
+                    let tone = body.next_with(freq, 0.0) / AMPLITUDE;
+                    (tone * decay(t, kick.decay) + click) * gain
+                }))
+            }
+            Drum::Snare(snare) => {
+                let mut low = BandLimited::new(context, Waveform::Sine, snare.tone_freq);
+                let mut high = BandLimited::new(context, Waveform::Sine, snare.tone_freq * 1.6);
+                let mut noise = white_noise(2).map(|v| v / AMPLITUDE);
+                let mut filter = OnePole::new(context);
+                let longest = snare.tone_decay.max(snare.noise_decay);
+                Box::new((0..length(context, longest)).map(move |index| {
+                    let t = time(index);
+                    let tone = (low.next().unwrap_or(0.0) + 0.5 * high.next().unwrap_or(0.0))
+                        / AMPLITUDE
+                        / 1.5;
+                    let white = noise.next().unwrap_or(0.0);
+                    let rattle = white - filter.process(white, snare.brightness); // high pass
+                    let tone = tone * decay(t, snare.tone_decay) * (1.0 - snare.snappy);
+                    let rattle = rattle * decay(t, snare.noise_decay) * snare.snappy;
+                    (tone + rattle) * gain
+                }))
+            }
+            Drum::HiHat(hat) => {
+                let mut squares = HAT_RATIOS
+                    .iter()
+                    .map(|ratio| BandLimited::new(context, Waveform::Square, hat.base_freq * ratio))
+                    .collect::<Vec<_>>();
+                let mut filter = OnePole::new(context);
+                Box::new((0..length(context, hat.decay)).map(move |index| {
+                    let cluster = squares
+                        .iter_mut()
+                        .map(|square| square.next().unwrap_or(0.0))
+                        .sum::<f32>()
+                        / AMPLITUDE
+                        / HAT_RATIOS.len() as f32;
+                    let metal = cluster - filter.process(cluster, hat.brightness); // high pass
+                    metal * 2.0 * decay(time(index), hat.decay) * gain
+                }))
+            }
+            Drum::Tom(tom) => {
+                let mut body = BandLimited::new(context, Waveform::Sine, tom.freq);
+                let mut noise = white_noise(3).map(|v| v / AMPLITUDE);
+                Box::new((0..length(context, tom.decay)).map(move |index| {
+                    let t = time(index);
+                    let freq = tom.freq * (1.0 - tom.bend * (1.0 - decay(t, tom.decay)));
+                    let tone = body.next_with(freq, 0.0) / AMPLITUDE;
+                    let skin = noise.next().unwrap_or(0.0) * 0.2 * decay(t, 0.01);
+                    (tone + skin) * decay(t, tom.decay) * gain
+                }))
+            }
+        }
+    }
+}
+
+#[test]
+fn test_hits_end() {
+    let context = AudioContext::default();
+    for drum in [
+        Drum::kick(),
+        Drum::snare(),
+        Drum::closed_hat(),
+        Drum::tom(100.0),
+    ]
+    .iter()
+    {
+        let hit = drum.hit(context, 1.0).collect::<Vec<_>>();
+        assert!(!hit.is_empty() && hit.len() < context.samples(3.0));
+        assert!(hit.iter().all(|v| v.abs() <= AMPLITUDE * 1.5));
+        assert!(hit.last().unwrap().abs() < AMPLITUDE * SILENCE * 2.0);
+    }
+}
+
+// adds every (step, drum, velocity) hit to `buffer`, `step_length` samples per step
+pub fn render_pattern(
+    context: AudioContext,
+    buffer: &mut Vec<f32>,
+    pattern: &[(usize, Drum, f32)],
+    step_length: usize,
+) {
+    for (step, drum, velocity) in pattern.iter() {
+        let offset = step * step_length;
+        for (index, sample) in drum.hit(context, *velocity).enumerate() {
+            if buffer.len() <= offset + index {
+                buffer.resize(offset + index + 1, 0.0);
+            }
+            buffer[offset + index] += sample;
+        }
+    }
+}
+
+pub fn run(context: AudioContext) -> Result<(), Box<dyn std::error::Error>> {
+    println!("15 :: rendering barka with drums");
+    let spec = context.wav_spec(1); // mono
+    let mut writer = hound::WavWriter::create(OUTPUT_FILE, spec)?;
+    let melody = make_barka::<i16>(context, &Pythagorean::new(A4 * 0.5), &Waveform::Triangle)?;
+
+    // one step per beat of the barka timeline, an eighth note, so bars line up with the melody
+    let timeline = barka_timeline();
+    let beat = timeline.beat_ticks(0);
+    let step_length = timeline.sample(context, beat);
+    let steps = (timeline.bar_ticks(0) / beat) as usize;
+    let bar = (0..steps)
+        .flat_map(|step| {
+            let mut hits = vec![(step, Drum::closed_hat(), 0.3)];
+            match step {
+                0 => hits.push((step, Drum::kick(), 1.0)),
+                step if step == steps / 2 => hits.push((step, Drum::snare(), 0.6)),
+                step if step == steps - 1 => hits.push((step, Drum::open_hat(), 0.3)),
+                _ => {}
+            }
+            hits
+        })
+        .collect::<Vec<_>>();
+    let bars = melody.len() / (steps * step_length);
+    let mut pattern = (0..bars)
+        .flat_map(|index| {
+            bar.iter()
+                .map(move |(step, drum, velocity)| (step + index * steps, *drum, *velocity))
+        })
+        .collect::<Vec<_>>();
+    // a tom fill into the last bar, if there is a bar before it
+    if let Some(fill_bar) = bars.checked_sub(2) {
+        pattern.extend(
+            [(3, 196.0), (2, 147.0), (1, 110.0)]
+                .iter()
+                .map(|(from_end, freq)| {
+                    let step = ((fill_bar + 1) * steps).saturating_sub(*from_end);
+                    (step, Drum::tom(*freq), 0.8)
+                }),
+        );
+    }
+
+    let mut drums = vec![0.0; melody.len()];
+    render_pattern(context, &mut drums, &pattern, step_length);
+    for (note, drum) in melody.into_iter().zip(drums) {
+        let mixed = note.to_synth() * 0.7 + drum * 0.5;
+        writer.write_sample(i16::from_synth(mixed))?;
+    }
+    Ok(())
+}
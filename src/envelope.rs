@@ -0,0 +1,362 @@
+use crate::context::AudioContext;
+use crate::lo_pass_filter::SweptLoPassFilter;
+use crate::oscillators::{saw_wave, Instrument, Waveform};
+use crate::pythagorean_chords::chord;
+use crate::sample::Sample;
+use crate::tuning::Edo;
+
+const OUTPUT_FILE: &str = "./output/envelopes.wav";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Curve {
+    Linear,
+    // positive values start slow and end fast, negative values the other way around
+    Exponential(f32),
+}
+
+impl Curve {
+    // maps progress through a segment (0.0 - 1.0) to progress towards its target
+    fn shape(self, x: f32) -> f32 {
+        match self {
+            Curve::Linear => x,
+            Curve::Exponential(k) if k.abs() < 1e-3 => x,
+            Curve::Exponential(k) => ((k * x).exp() - 1.0) / (k.exp() - 1.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Segment {
+    pub target: f32,
+    pub time: f32, // seconds
+    pub curve: Curve,
+}
+
+impl Segment {
+    pub fn linear(target: f32, time: f32) -> Self {
+        Self {
+            target,
+            time,
+            curve: Curve::Linear,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trigger {
+    Retrigger, // every note-on restarts the envelope from the current level
+    Legato,    // a note-on while the gate is open keeps the envelope where it is
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Envelope {
+    pub segments: Vec<Segment>,
+    pub sustain: Option<usize>, // segment whose target is held while the gate is open
+    pub release: Vec<Segment>,
+    pub trigger: Trigger,
+}
+
+impl Envelope {
+    pub fn adsr(attack: f32, decay: f32, sustain: f32, release: f32) -> Self {
+        Self {
+            segments: vec![
+                Segment::linear(1.0, attack),
+                Segment {
+                    target: sustain,
+                    time: decay,
+                    curve: Curve::Exponential(-4.0),
+                },
+            ],
+            sustain: Some(1),
+            release: vec![Segment {
+                target: 0.0,
+                time: release,
+                curve: Curve::Exponential(-4.0),
+            }],
+            trigger: Trigger::Retrigger,
+        }
+    }
+
+    pub fn ar(attack: f32, release: f32) -> Self {
+        Self {
+            segments: vec![Segment::linear(1.0, attack)],
+            sustain: Some(0),
+            release: vec![Segment::linear(0.0, release)],
+            trigger: Trigger::Retrigger,
+        }
+    }
+
+    // without a sustain segment the envelope runs straight into its release
+    pub fn multi_segment(
+        segments: Vec<Segment>,
+        sustain: Option<usize>,
+        release: Vec<Segment>,
+    ) -> Self {
+        Self {
+            segments,
+            sustain,
+            release,
+            trigger: Trigger::Retrigger,
+        }
+    }
+
+    pub fn legato(mut self) -> Self {
+        self.trigger = Trigger::Legato;
+        self
+    }
+
+    pub fn release_time(&self) -> f32 {
+        self.release.iter().map(|segment| segment.time).sum()
+    }
+
+    pub fn generator(&self, context: AudioContext) -> EnvelopeGenerator {
+        EnvelopeGenerator {
+            context,
+            envelope: self.clone(),
+            stage: Stage::Idle,
+            position: 0,
+            start_level: 0.0,
+            level: 0.0,
+        }
+    }
+
+    // opens the gate for `gate_length` samples, the note ends once the release is over
+    pub fn note<V: Iterator<Item = f32>>(
+        &self,
+        context: AudioContext,
+        voice: V,
+        gate_length: usize,
+    ) -> Note<V> {
+        let mut generator = self.generator(context);
+        generator.gate_on();
+        Note {
+            voice,
+            generator,
+            gate_length,
+            position: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Stage {
+    Idle,
+    Attack(usize),
+    Sustain,
+    Release(usize),
+    Done,
+}
+
+pub struct EnvelopeGenerator {
+    context: AudioContext,
+    envelope: Envelope,
+    stage: Stage,
+    position: usize, // samples into the current segment
+    start_level: f32,
+    level: f32,
+}
+
+impl EnvelopeGenerator {
+    fn enter(&mut self, stage: Stage) {
+        self.stage = stage;
+        self.position = 0;
+        self.start_level = self.level;
+    }
+
+    pub fn is_gated(&self) -> bool {
+        matches!(self.stage, Stage::Attack(_) | Stage::Sustain)
+    }
+
+    pub fn gate_on(&mut self) {
+        if !(self.is_gated() && self.envelope.trigger == Trigger::Legato) {
+            self.enter(Stage::Attack(0));
+        }
+    }
+
+    pub fn gate_off(&mut self) {
+        if !matches!(self.stage, Stage::Release(_) | Stage::Done) {
+            self.enter(Stage::Release(0));
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.stage == Stage::Done
+    }
+
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+
+    // where to go once the current segment reaches its target
+    fn following(&self) -> Stage {
+        match self.stage {
+            Stage::Attack(index) if Some(index) == self.envelope.sustain => Stage::Sustain,
+            Stage::Attack(index) if index + 1 < self.envelope.segments.len() => {
+                Stage::Attack(index + 1)
+            }
+            Stage::Attack(_) => Stage::Release(0),
+            Stage::Release(index) if index + 1 < self.envelope.release.len() => {
+                Stage::Release(index + 1)
+            }
+            Stage::Release(_) => Stage::Done,
+            stage => stage,
+        }
+    }
+}
+
+impl Iterator for EnvelopeGenerator {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let segment = match self.stage {
+            Stage::Attack(index) => self.envelope.segments.get(index),
+            Stage::Release(index) => self.envelope.release.get(index),
+            Stage::Idle | Stage::Sustain | Stage::Done => return Some(self.level),
+        };
+        let segment = match segment {
+            Some(segment) => *segment,
+            None => {
+                // empty segment list, nothing to run through
+                let following = self.following();
+                self.enter(following);
+                return Some(self.level);
+            }
+        };
+        let length = self.context.samples(segment.time);
+        self.level = match length {
+            0 => segment.target,
+            _ => {
+                let progress = segment.curve.shape(self.position as f32 / length as f32);
+                self.start_level + (segment.target - self.start_level) * progress
+            }
+        };
+        self.position += 1;
+        if self.position >= length {
+            self.level = segment.target;
+            let following = self.following();
+            self.enter(following);
+        }
+        Some(self.level)
+    }
+}
+
+#[test]
+fn test_adsr_levels() {
+    let context = AudioContext::default();
+    let envelope = Envelope::adsr(0.01, 0.01, 0.5, 0.01);
+    let gate = context.samples(0.1);
+    let levels = envelope
+        .note(context, std::iter::repeat(1.0), gate)
+        .collect::<Vec<_>>();
+    assert_eq!(levels.len(), gate + context.samples(0.01));
+    assert!((levels[gate - 1] - 0.5).abs() < 1e-6);
+    assert!(levels.iter().all(|level| *level >= 0.0 && *level <= 1.0));
+    assert!(levels.last().unwrap().abs() < 1e-6);
+}
+
+pub struct Note<V: Iterator<Item = f32>> {
+    voice: V,
+    generator: EnvelopeGenerator,
+    gate_length: usize,
+    position: usize,
+}
+
+impl<V: Iterator<Item = f32>> Iterator for Note<V> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position == self.gate_length {
+            self.generator.gate_off();
+        }
+        if self.generator.is_finished() {
+            return None;
+        }
+        self.position += 1;
+        let level = self.generator.next()?;
+        Some(self.voice.next()? * level)
+    }
+}
+
+// plays an instrument through an envelope, so that every note fades in and out
+pub struct Shaped<I: Instrument> {
+    pub instrument: I,
+    pub envelope: Envelope,
+    pub gate_length: usize,
+}
+
+impl<I: Instrument> Instrument for Shaped<I> {
+    fn voice(&self, context: AudioContext, freq: f32) -> Box<dyn Iterator<Item = f32>> {
+        Box::new(self.envelope.note(
+            context,
+            self.instrument.voice(context, freq),
+            self.gate_length,
+        ))
+    }
+}
+
+pub fn run(context: AudioContext) -> Result<(), Box<dyn std::error::Error>> {
+    println!("8 :: generating envelopes");
+    let spec = context.wav_spec(1); // mono
+    let mut writer = hound::WavWriter::create(OUTPUT_FILE, spec)?;
+    let note_length = context.samples(0.5);
+
+    // plucky amplitude envelope
+    let pluck = Envelope::adsr(0.005, 0.2, 0.3, 0.1);
+    for freq in [220.0, 275.0, 330.0, 440.0].iter() {
+        for sample in pluck.note(context, saw_wave(context, *freq), note_length) {
+            writer.write_sample(i16::from_synth(sample))?;
+        }
+    }
+
+    // the same envelope sweeping the cutoff of a low-pass filter
+    let sweep = Envelope::multi_segment(
+        vec![
+            Segment {
+                target: 1.0,
+                time: 0.05,
+                curve: Curve::Exponential(2.0),
+            },
+            Segment {
+                target: 0.1,
+                time: 0.6,
+                curve: Curve::Exponential(-3.0),
+            },
+        ],
+        Some(1),
+        vec![Segment::linear(0.0, 0.2)],
+    );
+    let mut cutoff = sweep.generator(context);
+    cutoff.gate_on();
+    let cutoff = cutoff.map(|level| 100.0 + level * 8000.0);
+    let filtered = SweptLoPassFilter::new(context, saw_wave(context, 110.0), cutoff);
+    for sample in Envelope::ar(0.005, 0.2).note(context, filtered, 2 * note_length) {
+        writer.write_sample(i16::from_synth(sample))?;
+    }
+
+    // a legato envelope keeps going when the next note starts before the last one ends
+    let mut swell = Envelope::adsr(0.3, 0.3, 0.6, 0.3)
+        .legato()
+        .generator(context);
+    for freq in [220.0, 247.5, 275.0].iter() {
+        swell.gate_on();
+        for sample in saw_wave(context, *freq).take(note_length) {
+            writer.write_sample(i16::from_synth(sample * swell.next().unwrap_or(0.0)))?;
+        }
+    }
+    swell.gate_off();
+    for sample in saw_wave(context, 275.0).take(context.samples(0.3)) {
+        writer.write_sample(i16::from_synth(sample * swell.next().unwrap_or(0.0)))?;
+    }
+
+    // every note of a chord through the pluck envelope
+    let shaped = Shaped {
+        instrument: Waveform::Saw,
+        envelope: pluck,
+        gate_length: note_length,
+    };
+    let major = chord(context, &Edo::twelve(220.0), vec![0, 4, 7], &shaped);
+    for sample in major.take(note_length + context.samples(0.1)) {
+        writer.write_sample(i16::from_synth(sample / 3.0))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,479 @@
+use std::path::Path;
+
+use crate::context::AudioContext;
+use crate::oscillators::{Instrument, Phasor};
+use crate::pythagorean_chords::{make_barka, AMPLITUDE};
+use crate::sample::Sample;
+use crate::tuning::{Pythagorean, A4};
+
+const MODULATION_DEPTH: f32 = 4.0; // modulation index of a modulator at full level
+const OUTPUT_FILE: &str = "./output/fm_barka.wav";
+const ALGORITHMS_FILE: &str = "./output/fm_algorithms.wav";
+
+// DX7-style envelope: the first three segments run after note-on, the level then
+// holds at `levels[2]` until release, where the fourth segment runs to `levels[3]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct OperatorEnvelope {
+    pub times: [f32; 4],  // seconds per segment
+    pub levels: [f32; 4], // 0.0 - 1.0
+}
+
+impl OperatorEnvelope {
+    pub fn sustained() -> Self {
+        Self {
+            times: [0.0, 0.0, 0.0, 0.0],
+            levels: [1.0, 1.0, 1.0, 0.0],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Operator {
+    pub ratio: f32,  // multiple of the note frequency
+    pub detune: f32, // Hz
+    pub level: f32,  // output level for carriers, modulation index for modulators
+    pub envelope: OperatorEnvelope,
+}
+
+impl Operator {
+    pub fn new(ratio: f32, level: f32) -> Self {
+        Self {
+            ratio,
+            detune: 0.0,
+            level,
+            envelope: OperatorEnvelope::sustained(),
+        }
+    }
+}
+
+// operators are numbered from 0, an operator can only be modulated by operators
+// with a higher number so that the whole graph renders in one pass per sample
+#[derive(Debug, Clone, PartialEq)]
+pub struct Algorithm {
+    pub modulations: Vec<(usize, usize)>, // (modulator, target)
+    pub carriers: Vec<usize>,
+    pub feedback: usize, // operator that modulates itself
+}
+
+impl Algorithm {
+    pub fn new(
+        modulations: Vec<(usize, usize)>,
+        carriers: Vec<usize>,
+        feedback: usize,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if carriers.is_empty() {
+            return Err("algorithm needs at least one carrier".into());
+        }
+        if let Some((modulator, target)) = modulations.iter().find(|(m, t)| m <= t) {
+            return Err(format!(
+                "operator {} cannot modulate operator {}, modulators need a higher number",
+                modulator, target
+            )
+            .into());
+        }
+        Ok(Self {
+            modulations,
+            carriers,
+            feedback,
+        })
+    }
+
+    pub fn operator_count(&self) -> usize {
+        self.modulations
+            .iter()
+            .flat_map(|(modulator, target)| vec![*modulator, *target])
+            .chain(self.carriers.iter().cloned())
+            .chain(std::iter::once(self.feedback))
+            .max()
+            .unwrap_or(0)
+            + 1
+    }
+
+    // DX7 algorithm 1: 6 -> 5 -> 4 -> 3 and 2 -> 1, feedback on 6
+    pub fn dx7_1() -> Self {
+        Self::new(vec![(5, 4), (4, 3), (3, 2), (1, 0)], vec![0, 2], 5).unwrap()
+    }
+
+    // DX7 algorithm 5: three pairs 6 -> 5, 4 -> 3, 2 -> 1, feedback on 6
+    pub fn dx7_5() -> Self {
+        Self::new(vec![(5, 4), (3, 2), (1, 0)], vec![0, 2, 4], 5).unwrap()
+    }
+
+    // DX7 algorithm 32: six sines summed, feedback on 6
+    pub fn dx7_32() -> Self {
+        Self::new(vec![], (0..6).collect(), 5).unwrap()
+    }
+
+    // a chain of `count` operators, each one modulating the next
+    pub fn stack(count: usize) -> Self {
+        let modulations = (1..count).map(|index| (index, index - 1)).collect();
+        Self::new(modulations, vec![0], count.max(1) - 1).unwrap()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Patch {
+    pub name: String,
+    pub algorithm: Algorithm,
+    pub operators: Vec<Operator>,
+    pub feedback: f32, // 0.0 - 1.0
+}
+
+fn parse_numbers<T: std::str::FromStr>(
+    values: &str,
+    line: usize,
+) -> Result<Vec<T>, Box<dyn std::error::Error>> {
+    values
+        .split_whitespace()
+        .map(|value| {
+            value
+                .parse::<T>()
+                .map_err(|_| format!("line {}: `{}` is not a valid number", line, value).into())
+        })
+        .collect()
+}
+
+fn expected(line: usize, what: &str) -> Box<dyn std::error::Error> {
+    format!("line {}: expected `{}`", line, what).into()
+}
+
+fn join<T: ToString>(values: &[T]) -> String {
+    values
+        .iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl Patch {
+    pub fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let needed = self.algorithm.operator_count();
+        if self.operators.len() < needed {
+            return Err(format!(
+                "patch `{}` has {} operators but its algorithm uses {}",
+                self.name,
+                self.operators.len(),
+                needed
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    // line based text format:
+    //   name <text>
+    //   carriers <operator>...
+    //   modulation <modulator> <target>
+    //   feedback <operator> <amount>
+    //   operator <ratio> <detune> <level> <4 times> <4 levels>
+    pub fn to_text(&self) -> String {
+        let mut lines = vec![
+            format!("name {}", self.name),
+            format!("carriers {}", join(&self.algorithm.carriers)),
+        ];
+        lines.extend(
+            self.algorithm
+                .modulations
+                .iter()
+                .map(|(modulator, target)| format!("modulation {} {}", modulator, target)),
+        );
+        lines.push(format!(
+            "feedback {} {}",
+            self.algorithm.feedback, self.feedback
+        ));
+        lines.extend(self.operators.iter().map(|operator| {
+            format!(
+                "operator {} {} {} {} {}",
+                operator.ratio,
+                operator.detune,
+                operator.level,
+                join(&operator.envelope.times),
+                join(&operator.envelope.levels)
+            )
+        }));
+        lines.join("\n") + "\n"
+    }
+
+    pub fn from_text(text: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut name = String::new();
+        let mut carriers = vec![];
+        let mut modulations = vec![];
+        let mut feedback = (0, 0.0);
+        let mut operators = vec![];
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, values) = match line.find(' ') {
+                Some(split) => (&line[..split], line[split..].trim()),
+                None => (line, ""),
+            };
+            match key {
+                "name" => name = values.to_string(),
+                "carriers" => carriers = parse_numbers(values, line_number)?,
+                "modulation" => match parse_numbers::<usize>(values, line_number)?[..] {
+                    [modulator, target] => modulations.push((modulator, target)),
+                    _ => return Err(expected(line_number, "modulation <modulator> <target>")),
+                },
+                "feedback" => match values.split_whitespace().collect::<Vec<_>>()[..] {
+                    [operator, amount] => {
+                        feedback = (
+                            parse_numbers::<usize>(operator, line_number)?[0],
+                            parse_numbers::<f32>(amount, line_number)?[0],
+                        )
+                    }
+                    _ => return Err(expected(line_number, "feedback <operator> <amount>")),
+                },
+                "operator" => match parse_numbers::<f32>(values, line_number)?[..] {
+                    [ratio, detune, level, t1, t2, t3, t4, l1, l2, l3, l4] => {
+                        operators.push(Operator {
+                            ratio,
+                            detune,
+                            level,
+                            envelope: OperatorEnvelope {
+                                times: [t1, t2, t3, t4],
+                                levels: [l1, l2, l3, l4],
+                            },
+                        })
+                    }
+                    _ => return Err(expected(line_number, "operator followed by 11 numbers")),
+                },
+                other => {
+                    return Err(format!("line {}: unknown key `{}`", line_number, other).into())
+                }
+            }
+        }
+        let patch = Self {
+            name,
+            algorithm: Algorithm::new(modulations, carriers, feedback.0)?,
+            operators,
+            feedback: feedback.1,
+        };
+        patch.validate()?;
+        Ok(patch)
+    }
+
+    pub fn save<T: AsRef<Path>>(&self, path: T) -> Result<(), Box<dyn std::error::Error>> {
+        std::fs::write(path, self.to_text())?;
+        Ok(())
+    }
+
+    pub fn load<T: AsRef<Path>>(path: T) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::from_text(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn electric_piano() -> Self {
+        let mut tine = Operator::new(14.0, 0.2);
+        tine.envelope = OperatorEnvelope {
+            times: [0.0, 0.3, 0.5, 0.2],
+            levels: [1.0, 0.2, 0.0, 0.0],
+        };
+        let mut body = Operator::new(1.0, 0.3);
+        body.envelope = OperatorEnvelope {
+            times: [0.0, 1.5, 2.0, 0.3],
+            levels: [1.0, 0.5, 0.3, 0.0],
+        };
+        let mut carrier = Operator::new(1.0, 1.0);
+        carrier.envelope = OperatorEnvelope {
+            times: [0.0, 2.0, 2.0, 0.3],
+            levels: [1.0, 0.7, 0.5, 0.0],
+        };
+        Self {
+            name: "electric piano".to_string(),
+            algorithm: Algorithm::dx7_5(),
+            operators: vec![
+                carrier.clone(),
+                tine,
+                carrier,
+                body,
+                Operator::new(1.0, 0.0),
+                Operator::new(1.0, 0.0),
+            ],
+            feedback: 0.0,
+        }
+    }
+}
+
+impl Instrument for Patch {
+    fn voice(&self, context: AudioContext, freq: f32) -> Box<dyn Iterator<Item = f32>> {
+        Box::new(FmVoice::new(context, self.clone(), freq))
+    }
+}
+
+#[test]
+fn test_patch_text_round_trip() {
+    let patch = Patch::electric_piano();
+    assert_eq!(Patch::from_text(&patch.to_text()).unwrap(), patch);
+    assert!(Patch::from_text("carriers 0\noperator 1 0 1").is_err());
+    assert!(Patch::from_text("carriers 0\nmodulation 0 1").is_err());
+
+    // fields are public, operators that aren't there play silence instead of panicking
+    let mut broken = patch;
+    broken.algorithm.modulations.push((9, 0));
+    broken.algorithm.carriers.push(12);
+    let voice = FmVoice::new(AudioContext::default(), broken, 220.0);
+    assert!(voice.take(100).all(|sample| sample.is_finite()));
+}
+
+struct EnvelopeState {
+    step: f32, // seconds per sample
+    segment: usize,
+    position: f32, // seconds into the current segment
+    start_level: f32,
+    level: f32,
+}
+
+impl EnvelopeState {
+    fn new(context: AudioContext) -> Self {
+        Self {
+            step: 1.0 / context.rate(),
+            segment: 0,
+            position: 0.0,
+            start_level: 0.0,
+            level: 0.0,
+        }
+    }
+
+    fn next(&mut self, envelope: &OperatorEnvelope) -> f32 {
+        let index = match self.segment {
+            0..=2 => self.segment,
+            4 => 3,
+            _ => return self.level, // sustaining, or done releasing
+        };
+        let time = envelope.times[index];
+        let target = envelope.levels[index];
+        self.level = if time <= 0.0 {
+            target
+        } else {
+            self.start_level + (target - self.start_level) * (self.position / time).min(1.0)
+        };
+        self.position += self.step;
+        if self.position >= time {
+            self.segment += 1;
+            self.position = 0.0;
+            self.start_level = self.level;
+        }
+        self.level
+    }
+
+    fn release(&mut self) {
+        self.segment = 4;
+        self.position = 0.0;
+        self.start_level = self.level;
+    }
+}
+
+pub struct FmVoice {
+    context: AudioContext,
+    patch: Patch,
+    freq: f32,
+    phasors: Vec<Phasor>,
+    envelopes: Vec<EnvelopeState>,
+    outputs: Vec<f32>,
+    feedback_history: [f32; 2],
+}
+
+impl FmVoice {
+    pub fn new(context: AudioContext, patch: Patch, freq: f32) -> Self {
+        let count = patch.operators.len();
+        Self {
+            context,
+            patch,
+            freq,
+            phasors: vec![Phasor::new(); count],
+            envelopes: (0..count).map(|_| EnvelopeState::new(context)).collect(),
+            outputs: vec![0.0; count],
+            feedback_history: [0.0; 2],
+        }
+    }
+
+    // moves every operator envelope into its release segment
+    pub fn release(&mut self) {
+        self.envelopes.iter_mut().for_each(EnvelopeState::release);
+    }
+}
+
+impl Iterator for FmVoice {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let algorithm = &self.patch.algorithm;
+        for index in (0..self.patch.operators.len()).rev() {
+            let operator = &self.patch.operators[index];
+            let mut modulation = algorithm
+                .modulations
+                .iter()
+                .filter(|(_, target)| *target == index)
+                // a patch built in code may point past its operators, those are silent
+                .map(|(modulator, _)| {
+                    self.outputs.get(*modulator).unwrap_or(&0.0) * MODULATION_DEPTH
+                })
+                .sum::<f32>();
+            if index == algorithm.feedback {
+                // averaging the last two outputs keeps high feedback from turning into noise
+                modulation += (self.feedback_history[0] + self.feedback_history[1]) / 2.0
+                    * self.patch.feedback
+                    * MODULATION_DEPTH;
+            }
+            let freq = self.freq * operator.ratio + operator.detune;
+            let phase = self.phasors[index].next_phase(self.context.increment(freq)) as f32;
+            let envelope = self.envelopes[index].next(&operator.envelope);
+            self.outputs[index] = ((phase + modulation) * 2.0 * std::f32::consts::PI).sin()
+                * operator.level
+                * envelope;
+            if index == algorithm.feedback {
+                self.feedback_history = [self.outputs[index], self.feedback_history[0]];
+            }
+        }
+        let carriers = &algorithm.carriers;
+        let mix = carriers
+            .iter()
+            .map(|index| self.outputs.get(*index).unwrap_or(&0.0))
+            .sum::<f32>();
+        Some(mix / carriers.len().max(1) as f32 * AMPLITUDE)
+    }
+}
+
+pub fn run(context: AudioContext) -> Result<(), Box<dyn std::error::Error>> {
+    println!("7 :: rendering barka with an FM patch");
+    let patch = Patch::electric_piano();
+    patch.save("./output/electric_piano.patch")?;
+    let patch = Patch::load("./output/electric_piano.patch")?;
+    let spec = context.wav_spec(1); // mono
+    let mut writer = hound::WavWriter::create(OUTPUT_FILE, spec)?;
+    for sample in make_barka::<i16>(context, &Pythagorean::new(A4 * 0.5), &patch)? {
+        writer.write_sample(sample)?;
+    }
+
+    // one held and released note per algorithm, every operator on the next harmonic
+    let mut writer = hound::WavWriter::create(ALGORITHMS_FILE, spec)?;
+    let mut envelope = OperatorEnvelope::sustained();
+    envelope.times = [0.01, 0.3, 0.3, 0.4];
+    envelope.levels = [1.0, 0.7, 0.6, 0.0];
+    for algorithm in [Algorithm::dx7_1(), Algorithm::dx7_32(), Algorithm::stack(4)].iter() {
+        let operators = (0..algorithm.operator_count())
+            .map(|index| {
+                let mut operator = Operator::new(index as f32 + 1.0, 0.5);
+                operator.envelope = envelope.clone();
+                operator
+            })
+            .collect();
+        let patch = Patch {
+            name: "harmonics".to_string(),
+            algorithm: algorithm.clone(),
+            operators,
+            feedback: 0.2,
+        };
+        let mut voice = FmVoice::new(context, patch, 220.0);
+        for sample in voice.by_ref().take(context.samples(1.0)) {
+            writer.write_sample(i16::from_synth(sample))?;
+        }
+        voice.release();
+        for sample in voice.take(context.samples(0.5)) {
+            writer.write_sample(i16::from_synth(sample))?;
+        }
+    }
+    Ok(())
+}
@@ -1,22 +1,20 @@
 use hound; // WAV codec library
 
-const SAMPLE_RATE: u32 = 44100;  // 44100 signal samples per second
+use crate::context::AudioContext;
+use crate::oscillators::Phasor;
+use crate::sample::Sample;
 
-pub fn run() -> Result<(), Box<dyn std::error::Error>> {
+pub fn run(context: AudioContext) -> Result<(), Box<dyn std::error::Error>> {
     println!("1 :: generating sinewave");
-    let spec = hound::WavSpec {
-        channels: 1, // mono
-        sample_rate: SAMPLE_RATE,
-        bits_per_sample: 16,
-        sample_format: hound::SampleFormat::Int,
-    };
+    let spec = context.wav_spec(1); // mono
     let mut writer = hound::WavWriter::create("./output/generate_sinewave.wav", spec)?;
     let length = 5; // seconds
-    let sample_length = length * SAMPLE_RATE;
-    for t in (0..(sample_length)).map(|x| x as f32 / SAMPLE_RATE as f32) {
-        let sample = (t * 440.0 * 2.0 * std::f32::consts::PI).sin();
-        let amplitude = std::i16::MAX as f32;
-        writer.write_sample((sample * amplitude) as i16)?;
+    let sample_length = length * context.sample_rate;
+    let mut phasor = Phasor::new();
+    for _ in 0..sample_length {
+        let phase = phasor.next_phase(context.increment(440.0));
+        let sample = (phase * 2.0 * std::f64::consts::PI).sin();
+        writer.write_sample(i16::from_f64(sample))?;
     }
     Ok(())
 }
@@ -0,0 +1,219 @@
+use rand::{Rng, SeedableRng};
+use rand_xorshift::XorShiftRng;
+
+use crate::context::AudioContext;
+use crate::pythagorean_chords::AMPLITUDE;
+use crate::sample::Sample;
+use crate::wavetable::read_first_channel;
+
+const OUTPUT_FILE: &str = "./output/granular.wav";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Window {
+    Hann,
+    Triangle,
+    Gaussian,
+    Rectangle,
+}
+
+impl Window {
+    // gain at `x` (0.0 - 1.0) through the grain
+    fn gain(self, x: f32) -> f32 {
+        match self {
+            Window::Hann => 0.5 - 0.5 * (2.0 * std::f32::consts::PI * x).cos(),
+            Window::Triangle => 1.0 - (2.0 * x - 1.0).abs(),
+            Window::Gaussian => (-0.5 * ((x - 0.5) / 0.15).powi(2)).exp(),
+            Window::Rectangle => 1.0,
+        }
+    }
+}
+
+// every field can be changed while the engine runs, new grains pick the change up
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GrainSettings {
+    pub size: f32,     // seconds
+    pub density: f32,  // grains per second
+    pub position: f32, // 0.0 - 1.0 through the source
+    pub pitch: f32,    // semitones
+    pub spray: f32,    // seconds of random offset around `position`
+    pub window: Window,
+}
+
+impl Default for GrainSettings {
+    fn default() -> Self {
+        Self {
+            size: 0.08,
+            density: 40.0,
+            position: 0.0,
+            pitch: 0.0,
+            spray: 0.01,
+            window: Window::Hann,
+        }
+    }
+}
+
+struct Grain {
+    start: f32, // position in the source, in samples
+    rate: f32,
+    length: usize,
+    age: usize,
+    window: Window,
+}
+
+pub struct Granulator {
+    context: AudioContext,
+    source: Vec<f32>,
+    pub settings: GrainSettings,
+    grains: Vec<Grain>,
+    until_next: f32, // samples until the next grain starts
+    rng: XorShiftRng,
+}
+
+impl Granulator {
+    pub fn new(
+        context: AudioContext,
+        source: Vec<f32>,
+        settings: GrainSettings,
+        seed: u64,
+    ) -> Self {
+        Self {
+            context,
+            source,
+            settings,
+            grains: vec![],
+            until_next: 0.0,
+            rng: XorShiftRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn from_wav<T: AsRef<std::path::Path>>(
+        path: T,
+        settings: GrainSettings,
+        seed: u64,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let (source, spec) = read_first_channel(path)?;
+        if source.is_empty() {
+            return Err("granular source is empty".into());
+        }
+        // grains play the source back at the rate it was recorded at
+        Ok(Self::new(
+            AudioContext::from_spec(&spec),
+            source,
+            settings,
+            seed,
+        ))
+    }
+
+    pub fn context(&self) -> AudioContext {
+        self.context
+    }
+
+    fn spawn(&mut self) {
+        let settings = self.settings;
+        let spray = settings.spray.abs() * self.context.rate();
+        let offset = if spray > 0.0 {
+            self.rng.gen_range(-spray..spray)
+        } else {
+            0.0
+        };
+        let start = settings.position.clamp(0.0, 1.0) * self.source.len() as f32 + offset;
+        self.grains.push(Grain {
+            start: start.max(0.0),
+            rate: 2.0f32.powf(settings.pitch / 12.0),
+            length: self.context.samples(settings.size).max(1),
+            age: 0,
+            window: settings.window,
+        });
+    }
+
+    // linear interpolation, silence outside of the source
+    fn read(&self, position: f32) -> f32 {
+        let index = position as usize;
+        match (self.source.get(index), self.source.get(index + 1)) {
+            (Some(one), Some(other)) => one + (other - one) * position.fract(),
+            (Some(one), None) => *one,
+            _ => 0.0,
+        }
+    }
+}
+
+impl Iterator for Granulator {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.until_next <= 0.0 {
+            self.spawn();
+            self.until_next += self.context.rate() / self.settings.density.max(0.1);
+        }
+        self.until_next -= 1.0;
+
+        let mix = self
+            .grains
+            .iter()
+            .map(|grain| {
+                let position = grain.start + grain.age as f32 * grain.rate;
+                let gain = grain.window.gain(grain.age as f32 / grain.length as f32);
+                self.read(position) * gain
+            })
+            .sum::<f32>();
+        self.grains.iter_mut().for_each(|grain| grain.age += 1);
+        self.grains.retain(|grain| grain.age < grain.length);
+
+        // overlapping grains add up, keep the level roughly constant
+        let overlap = (self.settings.density * self.settings.size).max(1.0);
+        Some(mix / overlap.sqrt() * AMPLITUDE)
+    }
+}
+
+#[test]
+fn test_grain_windows() {
+    for window in [Window::Hann, Window::Triangle, Window::Gaussian].iter() {
+        assert!(window.gain(0.0) < 0.05);
+        assert!((window.gain(0.5) - 1.0).abs() < 1e-6);
+    }
+}
+
+pub fn run() -> Result<(), Box<dyn std::error::Error>> {
+    println!("13 :: granular time stretch");
+    let mut granulator = Granulator::from_wav(
+        crate::lo_pass_filter::INPUT_FILE,
+        GrainSettings::default(),
+        0,
+    )?;
+    let context = granulator.context();
+    let mut writer = hound::WavWriter::create(OUTPUT_FILE, context.wav_spec(1))?; // mono
+
+    // the first quarter of the file, four times slower, then a pitched up shimmer
+    let length = context.samples(8.0);
+    for index in 0..length {
+        granulator.settings.position = 0.25 * index as f32 / length as f32;
+        writer.write_sample(i16::from_synth(granulator.next().unwrap_or(0.0)))?;
+    }
+    granulator.settings = GrainSettings {
+        size: 0.2,
+        density: 60.0,
+        position: 0.25,
+        pitch: 12.0,
+        spray: 0.3,
+        window: Window::Gaussian,
+    };
+    for sample in granulator.by_ref().take(context.samples(4.0)) {
+        writer.write_sample(i16::from_synth(sample))?;
+    }
+
+    // short sparse grains, the triangle still fades but the rectangle stutters
+    for window in [Window::Triangle, Window::Rectangle].iter() {
+        granulator.settings = GrainSettings {
+            size: 0.08,
+            density: 12.0,
+            position: 0.5,
+            pitch: 0.0,
+            spray: 0.05,
+            window: *window,
+        };
+        for sample in granulator.by_ref().take(context.samples(2.0)) {
+            writer.write_sample(i16::from_synth(sample))?;
+        }
+    }
+    Ok(())
+}
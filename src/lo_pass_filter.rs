@@ -2,32 +2,32 @@ use hound::{WavReader, WavWriter};
 use itertools::{Itertools, Tee};
 use ringbuf::{Consumer, Producer, RingBuffer};
 
+use crate::context::AudioContext;
+use crate::sample::{read_wav, write_sample, Sample};
+
 pub const INPUT_FILE: &str = "./data/audio-input.wav";
 const OUTPUT_FILE_LO_PASS: &str = "./output/after-lo-pass.wav";
 const OUTPUT_FILE_HI_PASS: &str = "./output/after-hi-pass.wav";
 
-const I24_MAX: i32 = (1 << 23) - 1;
-const I24_MIN: i32 = -I24_MAX;
-
 
-pub trait SignalFilter<T: Iterator<Item = i32>>: Sized + Iterator<Item = i32> {
+pub trait SignalFilter<S: Sample, T: Iterator<Item = S>>: Sized + Iterator<Item = S> {
     fn new(input: T, width: i32) -> Self;
 }
 
-struct HiPassFilter<T: Iterator<Item = i32>> {
-    lo_pass: LoPassFilter<Tee<T>>,
+struct HiPassFilter<S: Sample, T: Iterator<Item = S>> {
+    lo_pass: LoPassFilter<S, Tee<T>>,
     dry: Tee<T>,
 }
 
-impl<T: Iterator<Item = i32>> Iterator for HiPassFilter<T> {
-    type Item = i32;
+impl<S: Sample, T: Iterator<Item = S>> Iterator for HiPassFilter<S, T> {
+    type Item = S;
 
     fn next(&mut self) -> Option<Self::Item> {
-        Some(self.dry.next()? - self.lo_pass.next()?)
+        Some(S::from_f64(self.dry.next()?.to_f64() - self.lo_pass.next()?.to_f64()))
     }
 }
 
-impl<T: Iterator<Item = i32>> SignalFilter<T> for HiPassFilter<T> {
+impl<S: Sample, T: Iterator<Item = S>> SignalFilter<S, T> for HiPassFilter<S, T> {
     fn new(input: T, width: i32) -> Self {
         let (one, two) = input.tee();
         let lo_pass = LoPassFilter::new(one, width);
@@ -38,15 +38,15 @@ impl<T: Iterator<Item = i32>> SignalFilter<T> for HiPassFilter<T> {
     }
 }
 
-pub struct LoPassFilter<T: Iterator<Item = i32>> {
+pub struct LoPassFilter<S: Sample, T: Iterator<Item = S>> {
     pub input: T,
-    previous: i32,
+    previous: S,
     width: i32,
 }
 
-impl<T: Iterator<Item = i32>>  SignalFilter<T> for LoPassFilter<T> {
+impl<S: Sample, T: Iterator<Item = S>>  SignalFilter<S, T> for LoPassFilter<S, T> {
     fn new(input: T, width: i32) -> Self {
-        let previous = 0;
+        let previous = S::default();
 
         Self {
             input,
@@ -57,21 +57,14 @@ impl<T: Iterator<Item = i32>>  SignalFilter<T> for LoPassFilter<T> {
 }
 
 #[inline]
-fn weighted_average(one: i32, other: i32, ratio: f64) -> i32 {
+fn weighted_average<S: Sample>(one: S, other: S, ratio: f64) -> S {
     debug_assert!(ratio > 0.0);
     debug_assert!(ratio < 1.0);
-    ((one as f64) * ratio + (other as f64) * (1.0 - ratio)) as i32
+    S::from_f64(one.to_f64() * ratio + other.to_f64() * (1.0 - ratio))
 }
 
-fn one_minus(sample: i32) -> i32 {
-    match sample.is_positive() {
-        true => I24_MAX - sample,
-        false => I24_MIN - sample,
-    }
-}
-
-impl<T: Iterator<Item = i32>> Iterator for LoPassFilter<T> {
-    type Item = i32;
+impl<S: Sample, T: Iterator<Item = S>> Iterator for LoPassFilter<S, T> {
+    type Item = S;
 
     fn next(&mut self) -> Option<Self::Item> {
         let next = weighted_average(self.input.next()?, self.previous, 0.1f64.powi(self.width));
@@ -80,13 +73,61 @@ impl<T: Iterator<Item = i32>> Iterator for LoPassFilter<T> {
     }
 }
 
+// one pole low pass over any samples, the cutoff (Hz) can change on every sample
+#[derive(Debug, Clone, Copy)]
+pub struct OnePole {
+    context: AudioContext,
+    previous: f64,
+}
+
+impl OnePole {
+    pub fn new(context: AudioContext) -> Self {
+        Self {
+            context,
+            previous: 0.0,
+        }
+    }
+
+    pub fn process<S: Sample>(&mut self, input: S, cutoff: f32) -> S {
+        let omega = 2.0 * std::f32::consts::PI * cutoff.max(0.0) / self.context.rate();
+        let ratio = 1.0 - (-omega as f64).exp();
+        self.previous += (input.to_f64() - self.previous) * ratio;
+        S::from_f64(self.previous)
+    }
+}
+
+pub struct SweptLoPassFilter<S: Sample, T: Iterator<Item = S>, C: Iterator<Item = f32>> {
+    input: T,
+    cutoff: C,
+    filter: OnePole,
+}
+
+impl<S: Sample, T: Iterator<Item = S>, C: Iterator<Item = f32>> SweptLoPassFilter<S, T, C> {
+    pub fn new(context: AudioContext, input: T, cutoff: C) -> Self {
+        Self {
+            input,
+            cutoff,
+            filter: OnePole::new(context),
+        }
+    }
+}
+
+impl<S: Sample, T: Iterator<Item = S>, C: Iterator<Item = f32>> Iterator
+    for SweptLoPassFilter<S, T, C>
+{
+    type Item = S;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let cutoff = self.cutoff.next()?;
+        Some(self.filter.process(self.input.next()?, cutoff))
+    }
+}
+
 pub fn run() -> Result<(), Box<dyn std::error::Error
                                >> {
     println!("3 :: applying a lo pass filter");
-    let mut reader = hound::WavReader::open(INPUT_FILE)?;
-    let spec = reader.spec();
+    let (samples, spec) = read_wav::<f64, _>(INPUT_FILE)?;
     println!("{:#?}", spec);
-    let samples = reader.samples::<i32>().collect::<Result<Vec<i32>, _>>()?;
     let left = samples.iter().enumerate().filter(|(index, _value)| index % 2 == 0).map(|(_i, v)| *v).collect::<Vec<_>>();
     let right = samples.iter().enumerate().filter(|(index, _value)| index % 2 == 1).map(|(_i, v)| *v).collect::<Vec<_>>();
     let left_filter = LoPassFilter::new(left.into_iter(), 2);
@@ -94,15 +135,13 @@ pub fn run() -> Result<(), Box<dyn std::error::Error
 
     let mut writer = hound::WavWriter::create(OUTPUT_FILE_LO_PASS, spec)?;
     for (l, r) in  left_filter.into_iter().zip(right_filter.into_iter()) {
-        writer.write_sample(l)?;
-        writer.write_sample(l)?;
+        write_sample(&mut writer, spec, l)?;
+        write_sample(&mut writer, spec, l)?;
     }
 
     println!("4 :: applying a hi pass filter");
-    let mut reader = hound::WavReader::open(INPUT_FILE)?;
-    let spec = reader.spec();
+    let (samples, spec) = read_wav::<f64, _>(INPUT_FILE)?;
     println!("{:#?}", spec);
-    let samples = reader.samples::<i32>().collect::<Result<Vec<i32>, _>>()?;
     let left = samples.iter().enumerate().filter(|(index, _value)| index % 2 == 0).map(|(_i, v)| *v).collect::<Vec<_>>();
     let right = samples.iter().enumerate().filter(|(index, _value)| index % 2 == 1).map(|(_i, v)| *v).collect::<Vec<_>>();
     let left_filter = HiPassFilter::new(left.into_iter(), 2);
@@ -110,8 +149,8 @@ pub fn run() -> Result<(), Box<dyn std::error::Error
 
     let mut writer = hound::WavWriter::create(OUTPUT_FILE_HI_PASS, spec)?;
     for (l, r) in  left_filter.into_iter().zip(right_filter.into_iter()) {
-        writer.write_sample(l)?;
-        writer.write_sample(l)?;
+        write_sample(&mut writer, spec, l)?;
+        write_sample(&mut writer, spec, l)?;
     }
 
     Ok(())
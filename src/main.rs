@@ -1,14 +1,57 @@
 #![feature(min_type_alias_impl_trait)]
 
+mod context;
 mod generate_sinewave;
 mod pythagorean_chords;
 mod lo_pass_filter;
 mod plot_frequency;
+mod oscillators;
+mod wavetable;
+mod fm;
+mod envelope;
+mod noise;
+mod pluck;
+mod voices;
+mod modulation;
+mod granular;
+mod sampler;
+mod drums;
+mod sample;
+mod tuning;
+mod scala;
+mod pitch;
+mod tuning_report;
+mod temperament;
+mod scale;
+mod score;
+mod midi;
+mod sequencer;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    generate_sinewave::run()?;
-    pythagorean_chords::run()?;
+    let context = context::AudioContext::from_args()?;
+    generate_sinewave::run(context)?;
+    pythagorean_chords::run(context)?;
     lo_pass_filter::run()?;
     plot_frequency::run()?;
+    oscillators::run(context)?;
+    wavetable::run(context)?;
+    fm::run(context)?;
+    envelope::run(context)?;
+    noise::run(context)?;
+    pluck::run(context)?;
+    voices::run(context)?;
+    modulation::run(context)?;
+    granular::run()?;
+    sampler::run(context)?;
+    drums::run(context)?;
+    sample::run(context)?;
+    scala::run(context)?;
+    pitch::run(context)?;
+    tuning_report::run()?;
+    temperament::run(context)?;
+    scale::run(context)?;
+    score::run(context)?;
+    midi::run(context)?;
+    sequencer::run(context)?;
     Ok(())
 }
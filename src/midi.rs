@@ -0,0 +1,1067 @@
+use std::path::Path;
+
+use crate::context::AudioContext;
+use crate::envelope::Envelope;
+use crate::oscillators::Waveform;
+use crate::pitch::{cents, ConcertPitch, Keyboard, Note};
+use crate::sample::Sample;
+use crate::tuning::{Edo, Pythagorean, Tuning};
+use crate::voices::{Event, Stealing, VoiceManager};
+
+const INPUT_FILE: &str = "./data/barka.mid";
+const OUTPUT_FILE: &str = "./output/midi.wav";
+
+// General MIDI puts drums on channel 10, there are no pitches to tune there
+const PERCUSSION_CHANNEL: u8 = 9;
+const DEFAULT_TEMPO: u32 = 500_000; // microseconds per quarter note, 120 bpm
+const MAX_VOICES: usize = 16;
+const EXPORT_PPQ: u16 = 480;
+// MPE lower zone, channel 1 manages the zone and channels 2 - 16 play one note each
+const MEMBER_CHANNELS: u8 = 15;
+
+// how ticks turn into time, from the header
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Division {
+    // ticks per quarter note, the tempo map decides the rest
+    Ppq(u16),
+    // frames per second and ticks per frame, independent of tempo
+    Smpte { fps: u8, ticks: u8 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MidiMessage {
+    NoteOff {
+        channel: u8,
+        key: u8,
+        velocity: u8,
+    },
+    NoteOn {
+        channel: u8,
+        key: u8,
+        velocity: u8,
+    },
+    Controller {
+        channel: u8,
+        controller: u8,
+        value: u8,
+    },
+    ProgramChange {
+        channel: u8,
+        program: u8,
+    },
+    // -8192 - 8191, 0 is no bend
+    PitchBend {
+        channel: u8,
+        value: i16,
+    },
+    SysEx(Vec<u8>),
+    // microseconds per quarter note
+    Tempo(u32),
+    TimeSignature {
+        numerator: u8,
+        denominator: u8,
+    },
+    TrackName(String),
+    EndOfTrack,
+    // aftertouch and meta events nothing here needs
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackEvent {
+    pub tick: u64, // from the start of the track, not from the last event
+    pub message: MidiMessage,
+}
+
+// one note from note on to note off, with the program its channel had when it started
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MidiNote {
+    pub start: f32,
+    pub length: f32,
+    pub channel: u8,
+    pub key: i32,
+    pub velocity: f32,
+    pub program: u8,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MidiFile {
+    pub format: u16,
+    pub division: Division,
+    pub tracks: Vec<Vec<TrackEvent>>,
+}
+
+// reads big endian numbers off the file, errors say where it went wrong
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn error(&self, message: &str) -> Box<dyn std::error::Error> {
+        format!("byte {}: {}", self.position, message).into()
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], Box<dyn std::error::Error>> {
+        let remaining = self.bytes.len().saturating_sub(self.position);
+        if remaining < length {
+            return Err(self.error(&format!("file ends {} bytes early", length - remaining)));
+        }
+        let bytes = &self.bytes[self.position..self.position + length];
+        self.position += length;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, Box<dyn std::error::Error>> {
+        Ok(self.take(1)?[0])
+    }
+
+    // data bytes never have the top bit set
+    fn data(&mut self) -> Result<u8, Box<dyn std::error::Error>> {
+        let byte = self.byte()?;
+        if byte & 0x80 != 0 {
+            self.position -= 1;
+            return Err(self.error(&format!("expected a data byte, found {:#04x}", byte)));
+        }
+        Ok(byte)
+    }
+
+    fn u16(&mut self) -> Result<u16, Box<dyn std::error::Error>> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, Box<dyn std::error::Error>> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    // 7 bits per byte, the top bit says another one follows, at most 4 bytes
+    fn variable(&mut self) -> Result<u32, Box<dyn std::error::Error>> {
+        let mut value = 0;
+        for _ in 0..4 {
+            let byte = self.byte()?;
+            value = (value << 7) | (byte & 0x7f) as u32;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(self.error("variable length number is longer than 4 bytes"))
+    }
+}
+
+fn read_track(
+    reader: &mut Reader,
+    end: usize,
+) -> Result<Vec<TrackEvent>, Box<dyn std::error::Error>> {
+    let mut events = vec![];
+    let mut tick = 0u64;
+    let mut running_status = None;
+    while reader.position < end {
+        tick += reader.variable()? as u64;
+        let status = match reader.byte()? {
+            status if status & 0x80 != 0 => status,
+            // running status, the byte was already the first data byte
+            _ => {
+                reader.position -= 1;
+                running_status.ok_or_else(|| reader.error("data byte without a status byte"))?
+            }
+        };
+        let channel = status & 0x0f;
+        let message = match status {
+            0xff => {
+                running_status = None;
+                let kind = reader.byte()?;
+                let length = reader.variable()? as usize;
+                let data = reader.take(length)?;
+                match (kind, data) {
+                    (0x51, [a, b, c]) => MidiMessage::Tempo(u32::from_be_bytes([0, *a, *b, *c])),
+                    (0x58, [numerator, power, ..]) if *power < 8 => MidiMessage::TimeSignature {
+                        numerator: *numerator,
+                        denominator: 1 << power,
+                    },
+                    (0x03, name) => {
+                        MidiMessage::TrackName(String::from_utf8_lossy(name).to_string())
+                    }
+                    (0x2f, _) => MidiMessage::EndOfTrack,
+                    (0x51, _) | (0x58, _) => {
+                        return Err(
+                            reader.error(&format!("meta event {:#04x} has {} bytes", kind, length))
+                        )
+                    }
+                    _ => MidiMessage::Other,
+                }
+            }
+            0xf0 | 0xf7 => {
+                running_status = None;
+                let length = reader.variable()? as usize;
+                MidiMessage::SysEx(reader.take(length)?.to_vec())
+            }
+            0xf1..=0xfe => {
+                return Err(reader.error(&format!("{:#04x} doesn't belong in a file", status)))
+            }
+            _ => {
+                running_status = Some(status);
+                match status & 0xf0 {
+                    0x80 => MidiMessage::NoteOff {
+                        channel,
+                        key: reader.data()?,
+                        velocity: reader.data()?,
+                    },
+                    // note on with velocity 0 is a note off, it keeps running status going
+                    0x90 => match (reader.data()?, reader.data()?) {
+                        (key, 0) => MidiMessage::NoteOff {
+                            channel,
+                            key,
+                            velocity: 0,
+                        },
+                        (key, velocity) => MidiMessage::NoteOn {
+                            channel,
+                            key,
+                            velocity,
+                        },
+                    },
+                    0xb0 => MidiMessage::Controller {
+                        channel,
+                        controller: reader.data()?,
+                        value: reader.data()?,
+                    },
+                    0xc0 => MidiMessage::ProgramChange {
+                        channel,
+                        program: reader.data()?,
+                    },
+                    0xe0 => {
+                        let (low, high) = (reader.data()? as i16, reader.data()? as i16);
+                        MidiMessage::PitchBend {
+                            channel,
+                            value: (high << 7 | low) - 8192,
+                        }
+                    }
+                    // poly aftertouch has two data bytes, channel aftertouch one
+                    0xa0 => {
+                        reader.take(2)?;
+                        MidiMessage::Other
+                    }
+                    _ => {
+                        reader.take(1)?;
+                        MidiMessage::Other
+                    }
+                }
+            }
+        };
+        let end_of_track = message == MidiMessage::EndOfTrack;
+        events.push(TrackEvent { tick, message });
+        if end_of_track {
+            break;
+        }
+    }
+    if reader.position > end {
+        return Err(reader.error("event runs past the end of its track"));
+    }
+    reader.position = end;
+    Ok(events)
+}
+
+// how the cents that 12 tone equal temperament misses get to the synth
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Microtuning {
+    // MPE style, every note gets a member channel of its own and is bent
+    // by up to `range` semitones, the channels take turns
+    PitchBend { range: u8 },
+    // the keys that are played get retuned at the start with MIDI Tuning Standard
+    // single note tuning changes, notes stay on their own channels
+    TuningStandard,
+}
+
+fn write_variable(bytes: &mut Vec<u8>, value: u32) {
+    let mut groups = vec![(value & 0x7f) as u8];
+    let mut value = value >> 7;
+    while value > 0 {
+        groups.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.extend(groups.iter().rev());
+}
+
+// the bytes of one event after its delta time, `None` for what can't be written back
+fn message_bytes(message: &MidiMessage) -> Option<Vec<u8>> {
+    Some(match message {
+        MidiMessage::NoteOff {
+            channel,
+            key,
+            velocity,
+        } => vec![0x80 | channel, *key, *velocity],
+        MidiMessage::NoteOn {
+            channel,
+            key,
+            velocity,
+        } => vec![0x90 | channel, *key, *velocity],
+        MidiMessage::Controller {
+            channel,
+            controller,
+            value,
+        } => vec![0xb0 | channel, *controller, *value],
+        MidiMessage::ProgramChange { channel, program } => vec![0xc0 | channel, *program],
+        MidiMessage::PitchBend { channel, value } => {
+            let value = (value + 8192) as u16;
+            vec![0xe0 | channel, (value & 0x7f) as u8, (value >> 7) as u8]
+        }
+        // `F7` escapes come back as `F0`, they are read the same way
+        MidiMessage::SysEx(data) => {
+            let mut bytes = vec![0xf0];
+            write_variable(&mut bytes, data.len() as u32);
+            bytes.extend(data);
+            bytes
+        }
+        MidiMessage::Tempo(tempo) => {
+            let mut bytes = vec![0xff, 0x51, 0x03];
+            bytes.extend(&tempo.to_be_bytes()[1..]);
+            bytes
+        }
+        MidiMessage::TimeSignature {
+            numerator,
+            denominator,
+        } => vec![
+            0xff,
+            0x58,
+            0x04,
+            *numerator,
+            denominator.trailing_zeros() as u8,
+            24,
+            8,
+        ],
+        MidiMessage::TrackName(name) => {
+            let mut bytes = vec![0xff, 0x03];
+            write_variable(&mut bytes, name.len() as u32);
+            bytes.extend(name.as_bytes());
+            bytes
+        }
+        MidiMessage::EndOfTrack => vec![0xff, 0x2f, 0x00],
+        MidiMessage::Other => return None,
+    })
+}
+
+// closest key of 12 tone equal temperament at A4 = 440 Hz, what synths start out in,
+// and the cents `freq` is above it
+fn nearest_key(freq: f32) -> Result<(u8, f64), Box<dyn std::error::Error>> {
+    if !(freq.is_finite() && freq > 0.0) {
+        return Err(format!("{:.2} Hz is outside the MIDI keys", freq).into());
+    }
+    let note = ConcertPitch::default().note(freq);
+    if !(0..=127).contains(&note.key) {
+        return Err(format!("{:.2} Hz is outside the MIDI keys", freq).into());
+    }
+    Ok((note.key as u8, note.cents))
+}
+
+// real time single note tuning changes for every key and its frequency, for all devices
+// and tuning program 0, one message holds at most 127 keys
+pub fn tuning_sysex(keys: &[(u8, f32)]) -> Vec<Vec<u8>> {
+    keys.chunks(127)
+        .map(|chunk| {
+            let mut data = vec![0x7f, 0x7f, 0x08, 0x02, 0x00, chunk.len() as u8];
+            for (key, freq) in chunk {
+                // a semitone and 14 bits of fraction, `7f 7f 7f` means no change
+                let note = ConcertPitch::default().note(*freq);
+                let semitones = note.key as f64 + note.cents / 100.0;
+                let value = (semitones * 16384.0)
+                    .round()
+                    .max(0.0)
+                    .min((127 << 14 | 0x3ffe) as f64) as u32;
+                data.extend(&[
+                    *key,
+                    (value >> 14) as u8,
+                    (value >> 7 & 0x7f) as u8,
+                    (value & 0x7f) as u8,
+                ]);
+            }
+            data.push(0xf7);
+            data
+        })
+        .collect()
+}
+
+// controller messages that set registered parameter `parameter` of `channel`, then deselect it
+fn registered_parameter(tick: u64, channel: u8, parameter: u8, value: u8) -> Vec<TrackEvent> {
+    [
+        (101, 0),
+        (100, parameter),
+        (6, value),
+        (38, 0),
+        (101, 127),
+        (100, 127),
+    ]
+    .iter()
+    .map(|(controller, value)| TrackEvent {
+        tick,
+        message: MidiMessage::Controller {
+            channel,
+            controller: *controller,
+            value: *value,
+        },
+    })
+    .collect()
+}
+
+impl MidiFile {
+    pub fn parse(bytes: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut reader = Reader { bytes, position: 0 };
+        if reader.take(4)? != b"MThd" {
+            return Err("not a MIDI file, it doesn't start with `MThd`".into());
+        }
+        let header_length = reader.u32()? as usize;
+        if header_length < 6 || header_length > bytes.len() - reader.position {
+            return Err(reader.error(&format!(
+                "a header of {} bytes in a file of {}",
+                header_length,
+                bytes.len()
+            )));
+        }
+        let header_end = reader.position + header_length;
+        let format = reader.u16()?;
+        let track_count = reader.u16()?;
+        let division = match reader.u16()? {
+            division if division & 0x8000 == 0 => Division::Ppq(division),
+            // frames per second are stored negative
+            division => Division::Smpte {
+                fps: 0u8.wrapping_sub((division >> 8) as u8),
+                ticks: (division & 0xff) as u8,
+            },
+        };
+        if format > 2 {
+            return Err(reader.error(&format!("unknown format {}", format)));
+        }
+        if format == 2 {
+            return Err(reader.error("format 2, independent patterns, isn't supported"));
+        }
+        match division {
+            Division::Ppq(0) => return Err(reader.error("0 ticks per quarter note")),
+            Division::Smpte { fps, .. } if ![24, 25, 29, 30].contains(&fps) => {
+                return Err(reader.error(&format!("{} frames per second", fps)))
+            }
+            Division::Smpte { ticks: 0, .. } => return Err(reader.error("0 ticks per frame")),
+            _ => {}
+        }
+        reader.position = header_end;
+        let mut tracks = vec![];
+        while tracks.len() < track_count as usize {
+            let kind = reader.take(4)?;
+            let length = reader.u32()? as usize;
+            if length > bytes.len() - reader.position {
+                return Err(reader.error(&format!(
+                    "chunk of {} bytes runs past the end of the file",
+                    length
+                )));
+            }
+            let end = reader.position + length;
+            // chunks other than tracks are skipped, as the standard asks
+            if kind == b"MTrk" {
+                tracks.push(read_track(&mut reader, end)?);
+            } else {
+                reader.position = end;
+            }
+        }
+        Ok(Self {
+            format,
+            division,
+            tracks,
+        })
+    }
+
+    pub fn load<T: AsRef<Path>>(path: T) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::parse(&std::fs::read(path)?)
+    }
+
+    // `notes` as a format 1 file at a steady `tempo` in beats per minute,
+    // the conductor track holds the tempo and `meter` as a time signature
+    //
+    // the keys of the notes are indices of `tuning`, each one is played on the
+    // MIDI key closest to its frequency and `microtuning` makes up the difference
+    pub fn from_notes(
+        notes: &[MidiNote],
+        tuning: &impl Tuning,
+        microtuning: Microtuning,
+        tempo: f32,
+        meter: (u8, u8),
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        // a tempo is stored as 24 bits of microseconds per quarter note
+        let micros = (60e6 / tempo as f64).round();
+        if !(tempo.is_finite() && tempo > 0.0 && (1.0..=0xff_ffff as f64).contains(&micros)) {
+            return Err(format!("a tempo of {} beats per minute", tempo).into());
+        }
+        let (numerator, denominator) = meter;
+        if numerator == 0 || !denominator.is_power_of_two() {
+            return Err(format!("{}/{} is not a time signature", numerator, denominator).into());
+        }
+        let ticks = |seconds: f32| {
+            (seconds as f64 * tempo as f64 / 60.0 * EXPORT_PPQ as f64).round() as u64
+        };
+        let velocity = |velocity: f32| (velocity * 127.0).round().clamp(1.0, 127.0) as u8;
+        let mut notes = notes.to_vec();
+        notes.sort_by(|one, other| {
+            one.start
+                .partial_cmp(&other.start)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        let mut events = vec![];
+        let play = |channel: u8, key: u8, note: &MidiNote, events: &mut Vec<TrackEvent>| {
+            let velocity = velocity(note.velocity);
+            events.push(TrackEvent {
+                tick: ticks(note.start),
+                message: MidiMessage::NoteOn {
+                    channel,
+                    key,
+                    velocity,
+                },
+            });
+            events.push(TrackEvent {
+                tick: ticks(note.start + note.length),
+                message: MidiMessage::NoteOff {
+                    channel,
+                    key,
+                    velocity: 64,
+                },
+            });
+        };
+        match microtuning {
+            Microtuning::PitchBend { range } => {
+                if range == 0 || range > 96 {
+                    return Err(
+                        format!("a bend range of {} semitones, expected 1 - 96", range).into(),
+                    );
+                }
+                events.extend(registered_parameter(0, 0, 6, MEMBER_CHANNELS));
+                for channel in 1..=MEMBER_CHANNELS {
+                    events.extend(registered_parameter(0, channel, 0, range));
+                }
+                let mut free = [0u64; MEMBER_CHANNELS as usize];
+                let mut programs = [None; MEMBER_CHANNELS as usize];
+                for midi_note in notes.iter() {
+                    let (key, cents) = nearest_key(tuning.frequency(midi_note.key))?;
+                    let start = ticks(midi_note.start);
+                    // the channel that has been quiet the longest, or else the one that frees up first,
+                    // release tails keep their own bend for as long as possible
+                    let member = (0..free.len())
+                        .min_by_key(|member| (free[*member] > start, free[*member]))
+                        .unwrap_or(0);
+                    // bending a channel that is still playing would retune its note
+                    if free[member] > start {
+                        return Err(format!(
+                            "more than {} notes at {:.2} s, pitch bend needs a channel for each",
+                            MEMBER_CHANNELS, midi_note.start
+                        )
+                        .into());
+                    }
+                    free[member] = ticks(midi_note.start + midi_note.length);
+                    let channel = member as u8 + 1;
+                    if programs[member] != Some(midi_note.program) {
+                        programs[member] = Some(midi_note.program);
+                        events.push(TrackEvent {
+                            tick: start,
+                            message: MidiMessage::ProgramChange {
+                                channel,
+                                program: midi_note.program,
+                            },
+                        });
+                    }
+                    let bend = cents / (range as f64 * 100.0) * 8192.0;
+                    events.push(TrackEvent {
+                        tick: start,
+                        message: MidiMessage::PitchBend {
+                            channel,
+                            value: bend.round().clamp(-8192.0, 8191.0) as i16,
+                        },
+                    });
+                    play(channel, key, midi_note, &mut events);
+                }
+            }
+            Microtuning::TuningStandard => {
+                let mut retuned: Vec<(u8, f32)> = vec![];
+                let mut keys = vec![];
+                for midi_note in notes.iter() {
+                    let freq = tuning.frequency(midi_note.key);
+                    let (key, _) = nearest_key(freq)?;
+                    match retuned.iter().find(|(retuned_key, _)| *retuned_key == key) {
+                        Some((_, other)) if cents((freq / other) as f64).abs() > 0.01 => {
+                            return Err(format!(
+                                "{:.2} Hz and {:.2} Hz both need key {}, pitch bend can play them",
+                                other,
+                                freq,
+                                Note::new(key as i32)
+                            )
+                            .into())
+                        }
+                        Some(_) => {}
+                        None => retuned.push((key, freq)),
+                    }
+                    keys.push(key);
+                }
+                retuned.sort_by_key(|(key, _)| *key);
+                events.extend(tuning_sysex(&retuned).into_iter().map(|data| TrackEvent {
+                    tick: 0,
+                    message: MidiMessage::SysEx(data),
+                }));
+                let mut programs = [None; 16];
+                for (midi_note, key) in notes.iter().zip(keys) {
+                    let channel = midi_note.channel & 0x0f;
+                    if programs[channel as usize] != Some(midi_note.program) {
+                        programs[channel as usize] = Some(midi_note.program);
+                        events.push(TrackEvent {
+                            tick: ticks(midi_note.start),
+                            message: MidiMessage::ProgramChange {
+                                channel,
+                                program: midi_note.program,
+                            },
+                        });
+                    }
+                    play(channel, key, midi_note, &mut events);
+                }
+            }
+        }
+        // note offs first, a channel that is taken over must not bend the note that is ending
+        events.sort_by_key(|event| {
+            (
+                event.tick,
+                !matches!(event.message, MidiMessage::NoteOff { .. }),
+            )
+        });
+        let end = events.last().map_or(0, |event| event.tick);
+        events.push(TrackEvent {
+            tick: end,
+            message: MidiMessage::EndOfTrack,
+        });
+        let conductor = vec![
+            TrackEvent {
+                tick: 0,
+                message: MidiMessage::Tempo(micros as u32),
+            },
+            TrackEvent {
+                tick: 0,
+                message: MidiMessage::TimeSignature {
+                    numerator,
+                    denominator,
+                },
+            },
+            TrackEvent {
+                tick: 0,
+                message: MidiMessage::EndOfTrack,
+            },
+        ];
+        Ok(Self {
+            format: 1,
+            division: Division::Ppq(EXPORT_PPQ),
+            tracks: vec![conductor, events],
+        })
+    }
+
+    // tracks have to be sorted by tick, a missing end of track gets added
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = b"MThd".to_vec();
+        bytes.extend(&6u32.to_be_bytes());
+        bytes.extend(&self.format.to_be_bytes());
+        bytes.extend(&(self.tracks.len() as u16).to_be_bytes());
+        let division = match self.division {
+            Division::Ppq(ppq) => ppq,
+            Division::Smpte { fps, ticks } => (0u8.wrapping_sub(fps) as u16) << 8 | ticks as u16,
+        };
+        bytes.extend(&division.to_be_bytes());
+        for track in self.tracks.iter() {
+            let mut data = vec![];
+            let mut last = 0;
+            for event in track.iter() {
+                if let Some(message) = message_bytes(&event.message) {
+                    write_variable(&mut data, event.tick.saturating_sub(last) as u32);
+                    data.extend(message);
+                    last = event.tick;
+                }
+            }
+            if track.last().map(|event| &event.message) != Some(&MidiMessage::EndOfTrack) {
+                data.extend(&[0x00, 0xff, 0x2f, 0x00]);
+            }
+            bytes.extend(b"MTrk");
+            bytes.extend(&(data.len() as u32).to_be_bytes());
+            bytes.extend(data);
+        }
+        bytes
+    }
+
+    pub fn save<T: AsRef<Path>>(&self, path: T) -> Result<(), Box<dyn std::error::Error>> {
+        std::fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    // tempo changes of every track, in format 1 they are usually all on the first one
+    pub fn tempo_map(&self) -> Vec<(u64, u32)> {
+        let mut tempos = self
+            .tracks
+            .iter()
+            .flatten()
+            .filter_map(|event| match event.message {
+                MidiMessage::Tempo(tempo) => Some((event.tick, tempo)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        tempos.sort_by_key(|(tick, _)| *tick);
+        tempos
+    }
+
+    // seconds from the start at `tick`, following the tempo map
+    pub fn seconds(&self, tick: u64) -> f64 {
+        self.seconds_in(&self.tempo_map(), tick)
+    }
+
+    // `seconds` with the tempo map built once for many ticks
+    fn seconds_in(&self, tempo_map: &[(u64, u32)], tick: u64) -> f64 {
+        let ppq = match self.division {
+            Division::Ppq(ppq) => ppq as f64,
+            Division::Smpte { fps, ticks } => {
+                // 29 is drop frame 30 fps, 29.97 frames a second
+                let fps = if fps == 29 { 29.97 } else { fps as f64 };
+                return tick as f64 / (fps * ticks as f64);
+            }
+        };
+        let mut seconds = 0.0;
+        let mut last = (0, DEFAULT_TEMPO);
+        for &(change, tempo) in tempo_map {
+            if change >= tick {
+                break;
+            }
+            seconds += (change - last.0) as f64 * last.1 as f64 / 1e6 / ppq;
+            last = (change, tempo);
+        }
+        seconds + (tick - last.0) as f64 * last.1 as f64 / 1e6 / ppq
+    }
+
+    // every note of every track, sorted by start
+    //
+    // a note on for a key that is already down closes the older note first,
+    // notes still down at the end of their track end there
+    pub fn notes(&self) -> Vec<MidiNote> {
+        let tempo_map = self.tempo_map();
+        let mut notes = vec![];
+        for track in self.tracks.iter() {
+            let mut programs = [0u8; 16];
+            // start tick, velocity and program of every key that is down
+            let mut down: Vec<(u8, u8, u64, u8, u8)> = vec![];
+            let last_tick = track.last().map_or(0, |event| event.tick);
+            let mut close =
+                |channel: u8, key: u8, start: u64, velocity: u8, program: u8, end: u64| {
+                    let start_seconds = self.seconds_in(&tempo_map, start);
+                    notes.push(MidiNote {
+                        start: start_seconds as f32,
+                        length: (self.seconds_in(&tempo_map, end) - start_seconds) as f32,
+                        channel,
+                        key: key as i32,
+                        velocity: velocity as f32 / 127.0,
+                        program,
+                    });
+                };
+            for event in track.iter() {
+                match event.message {
+                    MidiMessage::ProgramChange { channel, program } => {
+                        programs[channel as usize] = program
+                    }
+                    MidiMessage::NoteOn {
+                        channel,
+                        key,
+                        velocity,
+                    } => {
+                        if let Some(index) = down.iter().position(|n| (n.0, n.1) == (channel, key))
+                        {
+                            let (channel, key, start, velocity, program) = down.remove(index);
+                            close(channel, key, start, velocity, program, event.tick);
+                        }
+                        down.push((
+                            channel,
+                            key,
+                            event.tick,
+                            velocity,
+                            programs[channel as usize],
+                        ));
+                    }
+                    MidiMessage::NoteOff { channel, key, .. } => {
+                        if let Some(index) = down.iter().position(|n| (n.0, n.1) == (channel, key))
+                        {
+                            let (channel, key, start, velocity, program) = down.remove(index);
+                            close(channel, key, start, velocity, program, event.tick);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            for (channel, key, start, velocity, program) in down {
+                close(channel, key, start, velocity, program, last_tick);
+            }
+        }
+        notes.sort_by(|one, other| {
+            one.start
+                .partial_cmp(&other.start)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        notes
+    }
+
+    // seconds until the last event of any track
+    pub fn length(&self) -> f32 {
+        let last = self
+            .tracks
+            .iter()
+            .filter_map(|track| track.last())
+            .map(|event| event.tick)
+            .max()
+            .unwrap_or(0);
+        self.seconds(last) as f32
+    }
+
+    // every pitched note with the oscillator for its program, keys are looked up in
+    // `tuning` as they are, so it should be a `Keyboard` or use MIDI keys in some other way
+    pub fn render(
+        &self,
+        context: AudioContext,
+        tuning: &impl Tuning,
+        envelope: &Envelope,
+    ) -> Vec<f32> {
+        let notes = self.notes();
+        let mut song = vec![];
+        let mut waveforms = vec![];
+        for note in notes
+            .iter()
+            .filter(|note| note.channel != PERCUSSION_CHANNEL)
+        {
+            if !waveforms.contains(&waveform(note.program)) {
+                waveforms.push(waveform(note.program));
+            }
+        }
+        for instrument in waveforms {
+            let mut events = vec![];
+            for note in notes.iter().filter(|note| {
+                note.channel != PERCUSSION_CHANNEL && waveform(note.program) == instrument
+            }) {
+                // the same key on two channels is two voices
+                let voice = note.channel as i32 * 128 + note.key;
+                let start = context.samples(note.start);
+                let freq = tuning.frequency(note.key);
+                events.push(Event::note_on(start, voice, freq, note.velocity));
+                events.push(Event::note_off(start + context.samples(note.length), voice));
+            }
+            let manager = VoiceManager::new(
+                context,
+                instrument,
+                envelope.clone(),
+                MAX_VOICES,
+                Stealing::SameNote,
+            );
+            for (index, sample) in manager.render(events).enumerate() {
+                match song.get_mut(index) {
+                    Some(mixed) => *mixed += sample,
+                    None => song.push(sample),
+                }
+            }
+        }
+        song
+    }
+}
+
+// an oscillator that sounds a little like a General MIDI program family
+pub fn waveform(program: u8) -> Waveform {
+    match program {
+        0..=15 => Waveform::Triangle,    // pianos and chromatic percussion
+        16..=23 => Waveform::Square,     // organs
+        24..=31 => Waveform::Pulse(0.3), // guitars
+        40..=55 => Waveform::Saw,        // strings and ensembles
+        56..=71 => Waveform::Pulse(0.2), // brass and reeds
+        _ => Waveform::Sine,             // pipes, basses, synths and the rest
+    }
+}
+
+#[test]
+fn test_midi_file() {
+    #[rustfmt::skip]
+    let bytes: Vec<u8> = vec![
+        b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 1, 0, 2, 0, 96,
+        // tempo track, 120 bpm, then 60 bpm after two quarters
+        b'M', b'T', b'r', b'k', 0, 0, 0, 19,
+        0x00, 0xff, 0x51, 0x03, 0x07, 0xa1, 0x20,
+        0x81, 0x40, 0xff, 0x51, 0x03, 0x0f, 0x42, 0x40,
+        0x00, 0xff, 0x2f, 0x00,
+        // organ, two notes with running status and a note on of velocity 0
+        b'M', b'T', b'r', b'k', 0, 0, 0, 22,
+        0x00, 0xc1, 16,
+        0x00, 0x91, 60, 100,
+        0x81, 0x40, 64, 127,
+        0x00, 60, 0,
+        0x60, 0x81, 64, 0,
+        0x00, 0xff, 0x2f, 0x00,
+    ];
+    let file = MidiFile::parse(&bytes).unwrap();
+    assert_eq!((file.format, file.division), (1, Division::Ppq(96)));
+    assert_eq!(file.tempo_map(), vec![(0, 500_000), (192, 1_000_000)]);
+    assert_eq!(file.seconds(192), 1.0);
+    assert_eq!(file.seconds(288), 2.0);
+    let notes = file.notes();
+    assert_eq!(notes.len(), 2);
+    assert_eq!(
+        (notes[0].key, notes[0].start, notes[0].length),
+        (60, 0.0, 1.0)
+    );
+    assert_eq!(
+        (notes[1].key, notes[1].start, notes[1].length),
+        (64, 1.0, 1.0)
+    );
+    assert_eq!(
+        (notes[1].velocity, notes[1].program, notes[1].channel),
+        (1.0, 16, 1)
+    );
+    assert_eq!(waveform(notes[1].program), Waveform::Square);
+    assert_eq!(file.length(), 2.0);
+
+    assert!(MidiFile::parse(b"RIFF").is_err());
+    let mut broken = bytes.clone();
+    broken.truncate(bytes.len() - 3);
+    assert!(MidiFile::parse(&broken)
+        .unwrap_err()
+        .to_string()
+        .starts_with("byte 49: chunk of 22 bytes"));
+    // broken headers are errors, not panics
+    let header = |length: u32, division: u16| {
+        let mut header = b"MThd".to_vec();
+        header.extend(&length.to_be_bytes());
+        header.extend(&[0, 0, 0, 0]);
+        header.extend(&division.to_be_bytes());
+        MidiFile::parse(&header)
+    };
+    assert!(header(0xffff_0000, 96).is_err());
+    assert!(header(2, 96).is_err());
+    assert!(header(6, 0x8000).is_err());
+    assert!(header(6, 0xe700).is_err());
+    assert!(header(6, 0xe728).unwrap().tracks.is_empty());
+}
+
+#[test]
+fn test_midi_export() {
+    let pythagorean = Pythagorean::new(220.0);
+    let note = |start, key| MidiNote {
+        start,
+        length: 0.5,
+        channel: 0,
+        key,
+        velocity: 1.0,
+        program: 73,
+    };
+    let notes = vec![note(0.0, 0), note(0.0, 7), note(0.5, 7)];
+    let bent = MidiFile::from_notes(
+        &notes,
+        &pythagorean,
+        Microtuning::PitchBend { range: 2 },
+        120.0,
+        (4, 4),
+    )
+    .unwrap();
+    assert_eq!(MidiFile::parse(&bent.to_bytes()).unwrap(), bent);
+    // the fifth is 1.955 cents wider than 700, every note gets a channel of its own
+    let bends = bent.tracks[1]
+        .iter()
+        .filter_map(|event| match event.message {
+            MidiMessage::PitchBend { channel, value } => Some((event.tick, channel, value)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(bends, vec![(0, 1, 0), (0, 2, 80), (480, 3, 80)]);
+    let played = bent.notes();
+    assert_eq!(
+        played
+            .iter()
+            .map(|note| (note.key, note.channel))
+            .collect::<Vec<_>>(),
+        vec![(57, 1), (64, 2), (64, 3)]
+    );
+    assert_eq!((played[2].start, played[2].length), (0.5, 0.5));
+
+    let retuned = MidiFile::from_notes(
+        &notes,
+        &pythagorean,
+        Microtuning::TuningStandard,
+        120.0,
+        (4, 4),
+    )
+    .unwrap();
+    assert_eq!(MidiFile::parse(&retuned.to_bytes()).unwrap(), retuned);
+    #[rustfmt::skip]
+    let sysex = vec![0x7f, 0x7f, 0x08, 0x02, 0x00, 2, 57, 57, 0, 0, 64, 64, 2, 64, 0xf7];
+    assert_eq!(retuned.tracks[1][0].message, MidiMessage::SysEx(sysex));
+    assert_eq!(retuned.notes().len(), 3);
+
+    // a quarter tone step and its neighbour end up on the same key
+    let quarter_tones = Edo::new(48, 220.0);
+    let error = MidiFile::from_notes(
+        &[note(0.0, 0), note(0.5, 1)],
+        &quarter_tones,
+        Microtuning::TuningStandard,
+        120.0,
+        (4, 4),
+    )
+    .unwrap_err();
+    assert!(error.to_string().contains("both need key A3"));
+    // 15 member channels hold 15 notes at once, not 16
+    let cluster = (0..16).map(|key| note(0.25, key)).collect::<Vec<_>>();
+    let bend = Microtuning::PitchBend { range: 2 };
+    assert!(MidiFile::from_notes(&cluster[..15], &pythagorean, bend, 120.0, (4, 4)).is_ok());
+    let error = MidiFile::from_notes(&cluster, &pythagorean, bend, 120.0, (4, 4)).unwrap_err();
+    assert!(error
+        .to_string()
+        .starts_with("more than 15 notes at 0.25 s"));
+    let meter = |file: &MidiFile| {
+        file.tracks[0].iter().find_map(|event| match event.message {
+            MidiMessage::TimeSignature {
+                numerator,
+                denominator,
+            } => Some((numerator, denominator)),
+            _ => None,
+        })
+    };
+    assert_eq!(meter(&bent), Some((4, 4)));
+    let waltz = MidiFile::from_notes(&notes, &pythagorean, bend, 120.0, (3, 4)).unwrap();
+    assert_eq!(
+        meter(&MidiFile::parse(&waltz.to_bytes()).unwrap()),
+        Some((3, 4))
+    );
+    // tempos have to fit 24 bits of microseconds per quarter note
+    for tempo in [0.0, -60.0, f32::NAN, 3.0].iter() {
+        assert!(MidiFile::from_notes(&notes, &pythagorean, bend, *tempo, (4, 4)).is_err());
+    }
+    assert!(MidiFile::from_notes(&notes, &pythagorean, bend, 120.0, (6, 6)).is_err());
+    assert!(MidiFile::from_notes(
+        &notes,
+        &pythagorean,
+        Microtuning::PitchBend { range: 0 },
+        120.0,
+        (4, 4)
+    )
+    .is_err());
+}
+
+pub fn run(context: AudioContext) -> Result<(), Box<dyn std::error::Error>> {
+    println!("23 :: rendering a MIDI file in two tunings");
+    let file = MidiFile::load(INPUT_FILE)?;
+    println!(
+        "format {}, {} tracks, {} notes, {:.1} s",
+        file.format,
+        file.tracks.len(),
+        file.notes().len(),
+        file.length()
+    );
+    // pythagorean on the left, equal temperament on the right, like barka
+    let tonic = Note::parse("A3")?;
+    let pythagorean = Keyboard::new(Pythagorean::new(1.0), tonic, ConcertPitch::default());
+    let equal = Keyboard::new(Edo::twelve(1.0), tonic, ConcertPitch::default());
+    let envelope = Envelope::adsr(0.01, 0.2, 0.7, 0.2);
+    let left = file.render(context, &pythagorean, &envelope);
+    let right = file.render(context, &equal, &envelope);
+    let spec = context.wav_spec(2);
+    let mut writer = hound::WavWriter::create(OUTPUT_FILE, spec)?;
+    for (left, right) in left.into_iter().zip(right) {
+        writer.write_sample(i16::from_synth(left / 2.0))?;
+        writer.write_sample(i16::from_synth(right / 2.0))?;
+    }
+    Ok(())
+}
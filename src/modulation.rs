@@ -0,0 +1,350 @@
+use rand::{Rng, SeedableRng};
+use rand_xorshift::XorShiftRng;
+
+use crate::context::AudioContext;
+use crate::envelope::{Envelope, EnvelopeGenerator};
+use crate::lo_pass_filter::OnePole;
+use crate::oscillators::{BandLimited, Phasor, Waveform};
+use crate::sample::Sample;
+
+const OUTPUT_FILE: &str = "./output/modulation.wav";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LfoShape {
+    Sine,
+    Triangle,
+    SampleAndHold, // a new random value every cycle
+    SmoothRandom,  // random values with cosine glides between them
+}
+
+// low frequency oscillator, outputs -1.0 - 1.0
+pub struct Lfo {
+    context: AudioContext,
+    shape: LfoShape,
+    rate: f32, // Hz
+    phasor: Phasor,
+    rng: XorShiftRng,
+    held: (f32, f32), // previous and current random value
+    previous_phase: f32,
+}
+
+impl Lfo {
+    pub fn new(context: AudioContext, shape: LfoShape, rate: f32, seed: u64) -> Self {
+        let mut rng = XorShiftRng::seed_from_u64(seed);
+        let held = (rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+        Self {
+            context,
+            shape,
+            rate,
+            phasor: Phasor::new(),
+            rng,
+            held,
+            previous_phase: 0.0,
+        }
+    }
+}
+
+impl Iterator for Lfo {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let increment = self.context.increment(self.rate);
+        let phase = self.phasor.next_phase(increment) as f32;
+        if phase < self.previous_phase {
+            // wrapped around, time for the next random value, from this sample on
+            self.held = (self.held.1, self.rng.gen_range(-1.0..1.0));
+        }
+        self.previous_phase = phase;
+        let value = match self.shape {
+            LfoShape::Sine => (phase * 2.0 * std::f32::consts::PI).sin(),
+            LfoShape::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            LfoShape::SampleAndHold => self.held.1,
+            LfoShape::SmoothRandom => {
+                let glide = (1.0 - (phase * std::f32::consts::PI).cos()) / 2.0;
+                self.held.0 + (self.held.1 - self.held.0) * glide
+            }
+        };
+        Some(value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Source {
+    Lfo(usize),
+    Envelope(usize),
+    Velocity,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Destination {
+    Pitch,     // semitones
+    Cutoff,    // octaves
+    Amplitude, // depth, a full source value leaves the gain untouched
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Route {
+    pub source: Source,
+    pub destination: Destination,
+    pub amount: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Modulation {
+    pub pitch: f32,
+    pub cutoff: f32,
+    pub amplitude: f32,
+}
+
+impl Default for Modulation {
+    fn default() -> Self {
+        Self {
+            pitch: 0.0,
+            cutoff: 0.0,
+            amplitude: 1.0,
+        }
+    }
+}
+
+pub struct ModulationMatrix {
+    lfos: Vec<Lfo>,
+    envelopes: Vec<EnvelopeGenerator>,
+    velocity: f32,
+    routes: Vec<Route>,
+    block_size: usize, // 1 updates every sample, larger values hold the values for a block
+    position: usize,
+    current: Modulation,
+    sources: (Vec<f32>, Vec<f32>),
+}
+
+impl ModulationMatrix {
+    pub fn new(
+        context: AudioContext,
+        lfos: Vec<Lfo>,
+        envelopes: Vec<Envelope>,
+        velocity: f32,
+        routes: Vec<Route>,
+    ) -> Self {
+        let sources = (vec![0.0; lfos.len()], vec![0.0; envelopes.len()]);
+        Self {
+            lfos,
+            envelopes: envelopes
+                .iter()
+                .map(|envelope| envelope.generator(context))
+                .collect(),
+            velocity,
+            routes,
+            block_size: 1,
+            position: 0,
+            current: Modulation::default(),
+            sources,
+        }
+    }
+
+    pub fn per_block(mut self, block_size: usize) -> Self {
+        self.block_size = block_size.max(1);
+        self
+    }
+
+    pub fn gate_on(&mut self) {
+        self.envelopes
+            .iter_mut()
+            .for_each(EnvelopeGenerator::gate_on);
+    }
+
+    pub fn gate_off(&mut self) {
+        self.envelopes
+            .iter_mut()
+            .for_each(EnvelopeGenerator::gate_off);
+    }
+
+    fn value(&self, source: Source) -> f32 {
+        match source {
+            Source::Lfo(index) => self.sources.0.get(index).cloned().unwrap_or(0.0),
+            Source::Envelope(index) => self.sources.1.get(index).cloned().unwrap_or(0.0),
+            Source::Velocity => self.velocity,
+        }
+    }
+}
+
+impl Iterator for ModulationMatrix {
+    type Item = Modulation;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // sources keep running every sample, only the routing is done per block
+        for (value, lfo) in self.sources.0.iter_mut().zip(self.lfos.iter_mut()) {
+            *value = lfo.next()?;
+        }
+        for (value, envelope) in self.sources.1.iter_mut().zip(self.envelopes.iter_mut()) {
+            *value = envelope.next()?;
+        }
+        if self.position.is_multiple_of(self.block_size) {
+            let mut modulation = Modulation::default();
+            for route in self.routes.iter() {
+                let value = self.value(route.source);
+                match route.destination {
+                    Destination::Pitch => modulation.pitch += value * route.amount,
+                    Destination::Cutoff => modulation.cutoff += value * route.amount,
+                    Destination::Amplitude => {
+                        modulation.amplitude *= (1.0 + route.amount * (value - 1.0)).max(0.0)
+                    }
+                }
+            }
+            self.current = modulation;
+        }
+        self.position += 1;
+        Some(self.current)
+    }
+}
+
+#[test]
+fn test_modulation_routing() {
+    let routes = vec![
+        Route {
+            source: Source::Velocity,
+            destination: Destination::Pitch,
+            amount: 12.0,
+        },
+        Route {
+            source: Source::Velocity,
+            destination: Destination::Amplitude,
+            amount: 1.0,
+        },
+    ];
+    let context = AudioContext::default();
+    let mut matrix = ModulationMatrix::new(context, vec![], vec![], 0.5, routes);
+    let modulation = matrix.next().unwrap();
+    assert_eq!(modulation.pitch, 6.0);
+    assert_eq!(modulation.amplitude, 0.5);
+
+    let lfo = Lfo::new(context, LfoShape::Triangle, 1.0, 0);
+    let values = lfo.take(context.samples(1.0)).collect::<Vec<_>>();
+    assert_eq!(values[0], -1.0);
+    assert!((values[context.samples(0.5)] - 1.0).abs() < 1e-3);
+
+    // the glide starts where the last one ended, no jumps when a cycle wraps
+    let smooth = Lfo::new(context, LfoShape::SmoothRandom, 5.0, 7)
+        .take(context.samples(2.0))
+        .collect::<Vec<_>>();
+    assert!(smooth
+        .windows(2)
+        .all(|pair| (pair[1] - pair[0]).abs() < 1e-3));
+    // and sample and hold changes once a cycle, 9 times in 2 seconds at 4.9 Hz
+    let held = Lfo::new(context, LfoShape::SampleAndHold, 4.9, 7)
+        .take(context.samples(2.0))
+        .collect::<Vec<_>>();
+    assert_eq!(held.windows(2).filter(|pair| pair[0] != pair[1]).count(), 9);
+}
+
+// oscillator -> low pass -> amplifier, with pitch, cutoff and amplitude driven by the matrix
+pub struct ModulatedVoice {
+    context: AudioContext,
+    oscillator: BandLimited,
+    filter: OnePole,
+    freq: f32,
+    cutoff: f32,
+    matrix: ModulationMatrix,
+}
+
+impl ModulatedVoice {
+    pub fn new(
+        context: AudioContext,
+        waveform: Waveform,
+        freq: f32,
+        cutoff: f32,
+        mut matrix: ModulationMatrix,
+    ) -> Self {
+        matrix.gate_on();
+        Self {
+            context,
+            oscillator: BandLimited::new(context, waveform, freq),
+            filter: OnePole::new(context),
+            freq,
+            cutoff,
+            matrix,
+        }
+    }
+
+    pub fn release(&mut self) {
+        self.matrix.gate_off();
+    }
+}
+
+impl Iterator for ModulatedVoice {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let modulation = self.matrix.next()?;
+        let freq = self.freq * 2.0f32.powf(modulation.pitch / 12.0);
+        let cutoff = (self.cutoff * 2.0f32.powf(modulation.cutoff)).min(self.context.nyquist());
+        let sample = self.oscillator.next_with(freq, 0.0);
+        Some(self.filter.process(sample, cutoff) * modulation.amplitude)
+    }
+}
+
+pub fn run(context: AudioContext) -> Result<(), Box<dyn std::error::Error>> {
+    println!("12 :: rendering modulated voices");
+    let spec = context.wav_spec(1); // mono
+    let mut writer = hound::WavWriter::create(OUTPUT_FILE, spec)?;
+    let length = context.samples(4.0);
+    for (shape, velocity) in [
+        (LfoShape::Sine, 1.0),
+        (LfoShape::Triangle, 0.8),
+        (LfoShape::SampleAndHold, 0.6),
+        (LfoShape::SmoothRandom, 1.0),
+    ]
+    .iter()
+    {
+        let routes = vec![
+            Route {
+                source: Source::Lfo(0),
+                destination: Destination::Pitch,
+                amount: 0.3, // vibrato
+            },
+            Route {
+                source: Source::Lfo(1),
+                destination: Destination::Cutoff,
+                amount: 2.0,
+            },
+            Route {
+                source: Source::Envelope(0),
+                destination: Destination::Cutoff,
+                amount: 3.0,
+            },
+            Route {
+                source: Source::Envelope(1),
+                destination: Destination::Amplitude,
+                amount: 1.0,
+            },
+            Route {
+                source: Source::Velocity,
+                destination: Destination::Amplitude,
+                amount: 0.5,
+            },
+        ];
+        let matrix = ModulationMatrix::new(
+            context,
+            vec![
+                Lfo::new(context, LfoShape::Sine, 5.0, 0),
+                Lfo::new(context, *shape, 2.0, 1),
+            ],
+            vec![
+                Envelope::adsr(0.01, 0.8, 0.2, 0.5),
+                Envelope::adsr(0.05, 0.3, 0.8, 0.5),
+            ],
+            *velocity,
+            routes,
+        )
+        .per_block(32);
+        let mut voice = ModulatedVoice::new(context, Waveform::Saw, 110.0, 200.0, matrix);
+        for index in 0..length {
+            if index == length - context.samples(0.5) {
+                voice.release();
+            }
+            if let Some(sample) = voice.next() {
+                writer.write_sample(i16::from_synth(sample))?;
+            }
+        }
+    }
+    Ok(())
+}
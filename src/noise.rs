@@ -0,0 +1,118 @@
+use rand::{Rng, SeedableRng};
+use rand_distr::{Distribution, Normal};
+use rand_xorshift::XorShiftRng;
+
+use crate::context::AudioContext;
+use crate::pythagorean_chords::AMPLITUDE;
+use crate::sample::Sample;
+
+const OUTPUT_FILE: &str = "./output/noise.wav";
+const PINK_ROWS: usize = 16; // octaves covered by the Voss-McCartney generator
+
+fn uniform(seed: u64) -> impl Iterator<Item = f32> {
+    let mut rng = XorShiftRng::seed_from_u64(seed);
+    std::iter::repeat_with(move || rng.gen_range(-1.0f32..1.0))
+}
+
+pub fn white_noise(seed: u64) -> impl Iterator<Item = f32> {
+    uniform(seed).map(|v| v * AMPLITUDE)
+}
+
+// clipped to +-1.0 before scaling, so keep `std_dev` around 0.3 or lower
+pub fn gaussian_noise(seed: u64, std_dev: f32) -> impl Iterator<Item = f32> {
+    let rng = XorShiftRng::seed_from_u64(seed);
+    let normal = Normal::new(0.0f32, std_dev.abs()).expect("standard deviation is finite");
+    normal
+        .sample_iter(rng)
+        .map(|v| v.clamp(-1.0, 1.0) * AMPLITUDE)
+}
+
+// Voss-McCartney: row `n` gets a new random value every 2^n samples,
+// summing all rows gives roughly -3 dB per octave
+pub fn pink_noise(seed: u64) -> impl Iterator<Item = f32> {
+    let mut rng = XorShiftRng::seed_from_u64(seed);
+    let mut rows = [0.0f32; PINK_ROWS];
+    rows.iter_mut()
+        .for_each(|row| *row = rng.gen_range(-1.0..1.0));
+    let mut counter = 0u32;
+    std::iter::repeat_with(move || {
+        counter = counter.wrapping_add(1);
+        let row = (counter.trailing_zeros() as usize).min(PINK_ROWS - 1);
+        rows[row] = rng.gen_range(-1.0..1.0);
+        let white = rng.gen_range(-1.0f32..1.0);
+        let sample = (rows.iter().sum::<f32>() + white) / (PINK_ROWS + 1) as f32 * 2.0;
+        sample.clamp(-1.0, 1.0) * AMPLITUDE
+    })
+}
+
+// integrated white noise, the leak keeps it from wandering off
+pub fn brown_noise(seed: u64) -> impl Iterator<Item = f32> {
+    let mut previous = 0.0f32;
+    uniform(seed).map(move |white| {
+        previous = (previous * 0.998 + white * 0.02).clamp(-1.0, 1.0);
+        previous * AMPLITUDE
+    })
+}
+
+// differentiated white noise, +3 dB per octave
+pub fn blue_noise(seed: u64) -> impl Iterator<Item = f32> {
+    let mut previous = 0.0f32;
+    uniform(seed).map(move |white| {
+        let sample = (white - previous) / 2.0;
+        previous = white;
+        sample * AMPLITUDE
+    })
+}
+
+// sparse random impulses, `density` per second with a random sign
+pub fn velvet_noise(context: AudioContext, seed: u64, density: f32) -> impl Iterator<Item = f32> {
+    let mut rng = XorShiftRng::seed_from_u64(seed);
+    let period = ((context.rate() / density.max(1.0)) as usize).max(1);
+    let mut impulse = (0, 0.0f32);
+    (0..).map(move |index: usize| {
+        if index.is_multiple_of(period) {
+            let sign = if rng.gen::<bool>() { 1.0 } else { -1.0 };
+            impulse = (index + rng.gen_range(0..period), sign);
+        }
+        if index == impulse.0 {
+            impulse.1 * AMPLITUDE
+        } else {
+            0.0
+        }
+    })
+}
+
+#[test]
+fn test_noise_is_deterministic() {
+    let one = pink_noise(7).take(1000).collect::<Vec<_>>();
+    assert_eq!(one, pink_noise(7).take(1000).collect::<Vec<_>>());
+    assert_ne!(one, pink_noise(8).take(1000).collect::<Vec<_>>());
+    let context = AudioContext::default();
+    let impulses = velvet_noise(context, 7, 2205.0)
+        .take(context.sample_rate as usize)
+        .filter(|v| *v != 0.0)
+        .count();
+    assert_eq!(impulses, 2205);
+}
+
+pub fn run(context: AudioContext) -> Result<(), Box<dyn std::error::Error>> {
+    println!("9 :: generating noise");
+    let spec = context.wav_spec(1); // mono
+    let mut writer = hound::WavWriter::create(OUTPUT_FILE, spec)?;
+    let length = context.sample_rate as usize; // one second of each
+    let seed = 42;
+    let sources: Vec<Box<dyn Iterator<Item = f32>>> = vec![
+        Box::new(white_noise(seed)),
+        Box::new(gaussian_noise(seed, 0.3)),
+        Box::new(pink_noise(seed)),
+        Box::new(brown_noise(seed)),
+        Box::new(blue_noise(seed)),
+        Box::new(velvet_noise(context, seed, 2000.0)),
+    ];
+    for source in sources {
+        for sample in source.take(length) {
+            writer.write_sample(i16::from_synth(sample))?;
+        }
+    }
+    Ok(())
+}
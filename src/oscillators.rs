@@ -0,0 +1,248 @@
+use crate::context::AudioContext;
+use crate::pythagorean_chords::{sine_wave, AMPLITUDE};
+use crate::sample::Sample;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Waveform {
+    Sine,
+    Saw,
+    Square,
+    Pulse(f32), // pulse width, 0.0 - 1.0
+    Triangle,
+}
+
+// anything that can start a voice at a given frequency, so that `chord()`
+// can mix any kind of sound source
+pub trait Instrument {
+    fn voice(&self, context: AudioContext, freq: f32) -> Box<dyn Iterator<Item = f32>>;
+}
+
+impl Instrument for Waveform {
+    fn voice(&self, context: AudioContext, freq: f32) -> Box<dyn Iterator<Item = f32>> {
+        self.wave(context, freq)
+    }
+}
+
+impl Waveform {
+    pub fn wave(self, context: AudioContext, freq: f32) -> Box<dyn Iterator<Item = f32>> {
+        match self {
+            Waveform::Sine => Box::new(sine_wave(context, freq)),
+            Waveform::Saw => Box::new(saw_wave(context, freq)),
+            Waveform::Square => Box::new(square_wave(context, freq)),
+            Waveform::Pulse(width) => Box::new(pulse_wave(context, freq, width)),
+            Waveform::Triangle => Box::new(triangle_wave(context, freq)),
+        }
+    }
+}
+
+// polynomial correction around a discontinuity, removes most of the aliasing
+// `t` is the phase (0.0 - 1.0), `dt` is the phase increment per sample
+fn poly_blep(t: f32, dt: f32) -> f32 {
+    if t < dt {
+        let t = t / dt;
+        t + t - t * t - 1.0
+    } else if t > 1.0 - dt {
+        let t = (t - 1.0) / dt;
+        t * t + t + t + 1.0
+    } else {
+        0.0
+    }
+}
+
+#[test]
+fn test_poly_blep() {
+    assert_eq!(poly_blep(0.5, 0.01), 0.0);
+    assert_eq!(poly_blep(0.0, 0.01), -1.0);
+    assert!((poly_blep(0.999999, 0.01) - 1.0).abs() < 0.001);
+}
+
+// phase accumulator wrapped to 0.0 - 1.0, kept in f64 so that the phase
+// stays accurate no matter how long the render is
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Phasor {
+    phase: f64,
+}
+
+impl Phasor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // returns the current phase and moves forward by `increment` cycles
+    pub fn next_phase(&mut self, increment: f64) -> f64 {
+        let phase = self.phase;
+        self.phase += increment;
+        self.phase -= self.phase.floor();
+        phase
+    }
+}
+
+#[test]
+fn test_phasor_hour_long_render() {
+    let context = AudioContext::default();
+    let freq = 441.3f32;
+    let samples = 3600 * context.sample_rate as u64; // one hour
+    let mut phasor = Phasor::new();
+    for _ in 0..samples {
+        phasor.next_phase(context.increment(freq));
+    }
+    let expected = (context.increment(freq) * samples as f64).fract();
+    assert!((phasor.next_phase(0.0) - expected).abs() < 1e-6);
+}
+
+pub struct BandLimited {
+    context: AudioContext,
+    waveform: Waveform,
+    phasor: Phasor,
+    increment: f64,
+    integrator: f32, // leaky integrator turning a square into a triangle
+}
+
+impl BandLimited {
+    pub fn new(context: AudioContext, waveform: Waveform, freq: f32) -> Self {
+        Self {
+            context,
+            waveform,
+            phasor: Phasor::new(),
+            increment: context.increment(freq),
+            integrator: -0.25, // start at the bottom of the triangle, no DC offset
+        }
+    }
+
+    // per-sample frequency (Hz) and phase (cycles) modulation inputs
+    pub fn modulated<F, P>(self, frequency: F, phase_modulation: P) -> Modulated<F, P>
+    where
+        F: Iterator<Item = f32>,
+        P: Iterator<Item = f32>,
+    {
+        Modulated {
+            oscillator: self,
+            frequency,
+            phase_modulation,
+        }
+    }
+
+    fn pulse(phase: f32, dt: f32, width: f32) -> f32 {
+        let width = width.clamp(0.0, 1.0);
+        let naive = if phase < width { 1.0 } else { -1.0 };
+        naive + poly_blep(phase, dt) - poly_blep((phase + 1.0 - width) % 1.0, dt)
+    }
+
+    // one sample at `freq` Hz with the phase pushed by `phase_offset` cycles
+    pub fn next_with(&mut self, freq: f32, phase_offset: f32) -> f32 {
+        self.increment = self.context.increment(freq);
+        self.sample(phase_offset as f64)
+    }
+
+    fn sample(&mut self, phase_offset: f64) -> f32 {
+        let phase = self.phasor.next_phase(self.increment) + phase_offset;
+        let phase = (phase - phase.floor()) as f32;
+        let dt = self.increment.abs() as f32;
+        let sample = match self.waveform {
+            Waveform::Sine => (phase * 2.0 * std::f32::consts::PI).sin(),
+            Waveform::Saw => 2.0 * phase - 1.0 - poly_blep(phase, dt),
+            Waveform::Square => Self::pulse(phase, dt, 0.5),
+            Waveform::Pulse(width) => Self::pulse(phase, dt, width),
+            Waveform::Triangle => {
+                let square = Self::pulse(phase, dt, 0.5);
+                self.integrator = dt * square + (1.0 - dt) * self.integrator;
+                4.0 * self.integrator
+            }
+        };
+        sample * AMPLITUDE
+    }
+}
+
+impl Iterator for BandLimited {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.sample(0.0))
+    }
+}
+
+#[test]
+fn test_modulated() {
+    let context = AudioContext::default();
+    let plain = BandLimited::new(context, Waveform::Saw, 220.0).take(1000);
+    let steady = BandLimited::new(context, Waveform::Saw, 220.0)
+        .modulated(std::iter::repeat(220.0), std::iter::repeat(0.0))
+        .take(1000);
+    assert!(plain.eq(steady));
+
+    // 441 Hz is 100 samples a cycle, a quarter cycle ahead is 25 samples ahead
+    let plain = BandLimited::new(context, Waveform::Sine, 441.0)
+        .skip(25)
+        .take(1000);
+    let shifted = BandLimited::new(context, Waveform::Sine, 441.0)
+        .modulated(std::iter::repeat(441.0), std::iter::repeat(0.25));
+    for (plain, shifted) in plain.zip(shifted) {
+        assert!((plain - shifted).abs() < 1e-3 * AMPLITUDE);
+    }
+}
+
+pub struct Modulated<F, P> {
+    oscillator: BandLimited,
+    frequency: F,
+    phase_modulation: P,
+}
+
+impl<F, P> Iterator for Modulated<F, P>
+where
+    F: Iterator<Item = f32>,
+    P: Iterator<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let freq = self.frequency.next()?;
+        let phase_offset = self.phase_modulation.next()?;
+        Some(self.oscillator.next_with(freq, phase_offset))
+    }
+}
+
+pub fn saw_wave(context: AudioContext, freq: f32) -> impl Iterator<Item = f32> {
+    BandLimited::new(context, Waveform::Saw, freq)
+}
+
+pub fn square_wave(context: AudioContext, freq: f32) -> impl Iterator<Item = f32> {
+    BandLimited::new(context, Waveform::Square, freq)
+}
+
+pub fn pulse_wave(context: AudioContext, freq: f32, width: f32) -> impl Iterator<Item = f32> {
+    BandLimited::new(context, Waveform::Pulse(width), freq)
+}
+
+pub fn triangle_wave(context: AudioContext, freq: f32) -> impl Iterator<Item = f32> {
+    BandLimited::new(context, Waveform::Triangle, freq)
+}
+
+pub fn run(context: AudioContext) -> Result<(), Box<dyn std::error::Error>> {
+    println!("5 :: generating band-limited oscillators");
+    let spec = context.wav_spec(1); // mono
+    let mut writer = hound::WavWriter::create("./output/oscillators.wav", spec)?;
+    let length = context.sample_rate as usize; // one second of each waveform
+    for waveform in [
+        Waveform::Sine,
+        Waveform::Saw,
+        Waveform::Square,
+        Waveform::Pulse(0.1),
+        Waveform::Triangle,
+    ]
+    .iter()
+    {
+        for sample in waveform.wave(context, 220.0).take(length) {
+            writer.write_sample(i16::from_synth(sample))?;
+        }
+    }
+    // a saw with 5 Hz vibrato, a third of a semitone either way
+    let vibrato = BandLimited::new(context, Waveform::Sine, 5.0)
+        .map(|v| 220.0 * 2.0f32.powf(v / AMPLITUDE / 36.0));
+    let saw = BandLimited::new(context, Waveform::Saw, 220.0)
+        .modulated(vibrato, std::iter::repeat(0.0))
+        .take(length);
+    for sample in saw {
+        writer.write_sample(i16::from_synth(sample))?;
+    }
+    Ok(())
+}
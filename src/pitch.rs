@@ -0,0 +1,373 @@
+use crate::context::AudioContext;
+use crate::envelope::Envelope;
+use crate::oscillators::Waveform;
+use crate::pythagorean_chords::chord;
+use crate::sample::Sample;
+use crate::tuning::{Edo, Pythagorean, Tuning, A4};
+
+const OUTPUT_FILE: &str = "./output/pitch.wav";
+
+const NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+pub fn cents(ratio: f64) -> f64 {
+    1200.0 * ratio.log2()
+}
+
+pub fn ratio_of_cents(cents: f64) -> f64 {
+    2.0f64.powf(cents / 1200.0)
+}
+
+// a MIDI key, 60 is C4 and 69 is A4, with an offset in cents
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Note {
+    pub key: i32,
+    pub cents: f64,
+}
+
+impl Note {
+    pub fn new(key: i32) -> Self {
+        Self { key, cents: 0.0 }
+    }
+
+    pub fn with_cents(self, cents: f64) -> Self {
+        Self { cents, ..self }
+    }
+
+    pub fn transpose(self, semitones: i32) -> Self {
+        Self {
+            key: self.key + semitones,
+            ..self
+        }
+    }
+
+    pub fn octave(&self) -> i32 {
+        self.key.div_euclid(12) - 1
+    }
+
+    // scientific pitch notation with sharps, e.g. `C#4` or `A4+15.0` with cents
+    pub fn name(&self) -> String {
+        let name = format!(
+            "{}{}",
+            NAMES[self.key.rem_euclid(12) as usize],
+            self.octave()
+        );
+        if self.cents == 0.0 {
+            name
+        } else {
+            format!("{}{:+.1}", name, self.cents)
+        }
+    }
+
+    // `C4`, `Bb3`, `F##2`, `C-1`, and a cents offset like `A4+15` or `Eb4-13.7`
+    pub fn parse(name: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let invalid = |why: &str| -> Box<dyn std::error::Error> {
+            format!("`{}` is not a note name, {}", name, why).into()
+        };
+        let mut chars = name.trim().char_indices().peekable();
+        let pitch_class = match chars.next().map(|(_, c)| c.to_ascii_uppercase()) {
+            Some('C') => 0,
+            Some('D') => 2,
+            Some('E') => 4,
+            Some('F') => 5,
+            Some('G') => 7,
+            Some('A') => 9,
+            Some('B') => 11,
+            _ => return Err(invalid("expected a letter A - G")),
+        };
+        let mut accidentals = 0;
+        while let Some((_, c)) = chars.peek() {
+            match c {
+                '#' => accidentals += 1,
+                'b' => accidentals -= 1,
+                _ => break,
+            }
+            chars.next();
+        }
+        let rest = match chars.peek() {
+            Some((index, _)) => &name.trim()[*index..],
+            None => return Err(invalid("expected an octave number")),
+        };
+        // the octave is a number right after the letter, it can be negative
+        let digits = rest
+            .char_indices()
+            .find(|(index, c)| !(c.is_ascii_digit() || (*index == 0 && *c == '-')))
+            .map_or(rest.len(), |(index, _)| index);
+        let octave = rest[..digits]
+            .parse::<i32>()
+            .map_err(|_| invalid("expected an octave number"))?;
+        let cents = match &rest[digits..] {
+            "" => 0.0,
+            offset if offset.starts_with('+') || offset.starts_with('-') => offset
+                .trim_start_matches('+')
+                .parse::<f64>()
+                .map_err(|_| invalid("expected cents like `+15` after the octave"))?,
+            _ => return Err(invalid("expected cents like `+15` after the octave")),
+        };
+        Ok(Self {
+            key: (octave + 1) * 12 + pitch_class + accidentals,
+            cents,
+        })
+    }
+}
+
+impl std::fmt::Display for Note {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl std::str::FromStr for Note {
+    type Err = Box<dyn std::error::Error>;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Self::parse(name)
+    }
+}
+
+// MIDI keys of whitespace separated note names, e.g. `"D4 F#4 A4"`
+pub fn keys(names: &str) -> Result<Vec<i32>, Box<dyn std::error::Error>> {
+    names
+        .split_whitespace()
+        .enumerate()
+        .map(|(index, name)| {
+            Note::parse(name)
+                .map(|note| note.key)
+                .map_err(|error| format!("note {}: {}", index + 1, error).into())
+        })
+        .collect()
+}
+
+// the frequency A4 is tuned to, everything else follows in equal temperament
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConcertPitch {
+    pub a4: f32,
+}
+
+impl Default for ConcertPitch {
+    fn default() -> Self {
+        Self::new(A4)
+    }
+}
+
+impl ConcertPitch {
+    pub const BAROQUE: Self = Self { a4: 415.0 };
+    pub const VERDI: Self = Self { a4: 432.0 };
+    pub const ORCHESTRA: Self = Self { a4: 442.0 };
+
+    pub fn new(a4: f32) -> Self {
+        Self { a4 }
+    }
+
+    pub fn frequency(&self, note: Note) -> f32 {
+        let semitones = (note.key - 69) as f64 + note.cents / 100.0;
+        (self.a4 as f64 * 2.0f64.powf(semitones / 12.0)) as f32
+    }
+
+    // nearest key, and how far `freq` is from it
+    pub fn note(&self, freq: f32) -> Note {
+        let semitones = 12.0 * (freq as f64 / self.a4 as f64).log2();
+        let key = semitones.round();
+        Note::new(69 + key as i32).with_cents(100.0 * (semitones - key))
+    }
+}
+
+// plays any tuning from MIDI keys, `tonic` sounds at its equal tempered
+// frequency and every key above it is one step of the tuning higher
+//
+// note indices are MIDI keys, so `chord()` and `make_barka` can take note names
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keyboard<T: Tuning> {
+    pub tuning: T,
+    pub tonic: Note,
+    pub concert_pitch: ConcertPitch,
+}
+
+impl<T: Tuning> Keyboard<T> {
+    pub fn new(tuning: T, tonic: Note, concert_pitch: ConcertPitch) -> Self {
+        Self {
+            tuning,
+            tonic,
+            concert_pitch,
+        }
+    }
+
+    pub fn note(&self, note: Note) -> f32 {
+        (self.frequency(note.key) as f64 * ratio_of_cents(note.cents)) as f32
+    }
+
+    pub fn named(&self, name: &str) -> Result<f32, Box<dyn std::error::Error>> {
+        Ok(self.note(Note::parse(name)?))
+    }
+
+    // ratio of `index` of the tuning underneath to its note 0, the tonic
+    fn inner_ratio(&self, index: i32) -> f64 {
+        let steps = self.tuning.steps() as i32;
+        let (period, step) = (index.div_euclid(steps), index.rem_euclid(steps));
+        self.tuning.ratio(step as usize) * self.tuning.period().powi(period)
+    }
+}
+
+impl<T: Tuning> Tuning for Keyboard<T> {
+    fn name(&self) -> String {
+        format!(
+            "{} on {}, A4 = {} Hz",
+            self.tuning.name(),
+            self.tonic,
+            self.concert_pitch.a4
+        )
+    }
+
+    fn base(&self) -> f32 {
+        self.frequency(0)
+    }
+
+    fn steps(&self) -> usize {
+        self.tuning.steps()
+    }
+
+    // counted from key 0 like `base()`, not from the tonic
+    fn ratio(&self, step: usize) -> f64 {
+        let tonic = self.tonic.key;
+        self.inner_ratio(step as i32 - tonic) / self.inner_ratio(-tonic)
+    }
+
+    fn period(&self) -> f64 {
+        self.tuning.period()
+    }
+
+    fn cents(&self, key: i32) -> f64 {
+        self.tuning.cents(key - self.tonic.key) - self.tuning.cents(-self.tonic.key)
+    }
+
+    fn frequency(&self, key: i32) -> f32 {
+        let tonic = self.concert_pitch.frequency(self.tonic) as f64;
+        let ratio = self.tuning.frequency(key - self.tonic.key) as f64 / self.tuning.base() as f64;
+        (tonic * ratio) as f32
+    }
+}
+
+#[test]
+fn test_pitch() {
+    assert_eq!(Note::parse("A4").unwrap(), Note::new(69));
+    assert_eq!(Note::parse("C-1").unwrap(), Note::new(0));
+    assert_eq!(Note::parse("Bb3").unwrap(), Note::new(58));
+    assert_eq!(Note::parse("B#3").unwrap(), Note::new(60));
+    assert_eq!(
+        Note::parse("Eb4-13.5").unwrap(),
+        Note::new(63).with_cents(-13.5)
+    );
+    assert_eq!(Note::new(61).name(), "C#4");
+    assert_eq!(Note::new(69).with_cents(15.0).to_string(), "A4+15.0");
+    for name in ["H4", "C", "C#x", "A4+", "Cb"].iter() {
+        assert!(Note::parse(name).is_err(), "{}", name);
+    }
+    assert!(keys("C4 E4 G4 X4")
+        .unwrap_err()
+        .to_string()
+        .starts_with("note 4:"));
+
+    let baroque = ConcertPitch::BAROQUE;
+    assert_eq!(baroque.frequency(Note::new(81)), 830.0);
+    let note = baroque.note(440.0);
+    assert_eq!(note.key, 70);
+    assert!((note.cents - 1.27).abs() < 0.01);
+    assert!(
+        (ConcertPitch::default().frequency(Note::new(69).with_cents(1200.0)) - 880.0).abs() < 1e-3
+    );
+
+    // a pythagorean fifth above D, wherever D is
+    let keyboard = Keyboard::new(
+        Pythagorean::new(1.0),
+        Note::parse("D4").unwrap(),
+        ConcertPitch::default(),
+    );
+    let d = keyboard.named("D4").unwrap();
+    assert!((d - ConcertPitch::default().frequency(Note::new(62))).abs() < 1e-3);
+    assert!((keyboard.named("A4").unwrap() / d - 1.5).abs() < 1e-6);
+    assert!((keyboard.named("D3").unwrap() * 2.0 - d).abs() < 1e-3);
+    let edo = Keyboard::new(Edo::new(19, 1.0), Note::new(60), ConcertPitch::default());
+    assert!((edo.frequency(79) / edo.frequency(60) - 2.0).abs() < 1e-6);
+    // the trait methods agree with each other whatever the tonic
+    let a3 = Keyboard::new(
+        Pythagorean::new(1.0),
+        Note::new(57),
+        ConcertPitch::default(),
+    );
+    for key in 0..24 {
+        let step = key as usize % 12;
+        let period = a3.period().powi(key / 12);
+        let frequency = a3.base() as f64 * a3.ratio(step) * period;
+        assert!((frequency / a3.frequency(key) as f64 - 1.0).abs() < 1e-6);
+        assert!((1200.0 * (a3.ratio(step) * period).log2() - a3.cents(key)).abs() < 1e-9);
+    }
+}
+
+pub fn run(context: AudioContext) -> Result<(), Box<dyn std::error::Error>> {
+    println!("18 :: playing note names at different concert pitches");
+    // baroque pythagorean on D on the left, modern orchestra on the right
+    let baroque = Keyboard::new(
+        Pythagorean::new(1.0),
+        Note::parse("D4")?,
+        ConcertPitch::BAROQUE,
+    );
+    let modern = Keyboard::new(
+        Edo::twelve(1.0),
+        Note::parse("D4")?,
+        ConcertPitch::ORCHESTRA,
+    );
+    let melody = keys("D4 F#4 A4 D5 C#5 A4 B4 G4 F#4 E4 D4")?;
+    let note_length = context.samples(0.4);
+    let envelope = Envelope::adsr(0.01, 0.1, 0.7, 0.1);
+    let release = context.samples(envelope.release_time());
+    let render = |tuning: &dyn Fn(i32) -> f32| {
+        melody
+            .iter()
+            .flat_map(|key| {
+                envelope
+                    .note(
+                        context,
+                        Waveform::Triangle.wave(context, tuning(*key)),
+                        note_length - release,
+                    )
+                    .chain(std::iter::repeat(0.0))
+                    .take(note_length)
+            })
+            .collect::<Vec<_>>()
+    };
+    let left = render(&|key| baroque.frequency(key));
+    let right = render(&|key| modern.frequency(key));
+    println!(
+        "A4 is {:.2} Hz on the left and {:.2} Hz on the right",
+        baroque.named("A4")?,
+        modern.named("A4")?
+    );
+    // the same A4 at other concert pitches, named at A4 = 440 Hz
+    let a4 = Note::parse("A4")?;
+    for pitch in [ConcertPitch::BAROQUE, ConcertPitch::VERDI].iter() {
+        let heard = ConcertPitch::default().note(pitch.frequency(a4));
+        println!(
+            "A4 at {} Hz is {}, the next key up is {}",
+            pitch.a4,
+            heard.name(),
+            heard.transpose(1).name()
+        );
+    }
+
+    let spec = context.wav_spec(2);
+    let mut writer = hound::WavWriter::create(OUTPUT_FILE, spec)?;
+    for (left, right) in left.into_iter().zip(right) {
+        writer.write_sample(i16::from_synth(left))?;
+        writer.write_sample(i16::from_synth(right))?;
+    }
+    // and a chord straight from note names
+    for sample in
+        chord(context, &baroque, keys("D3 A3 D4 F#4")?, &Waveform::Sine).take(context.samples(2.0))
+    {
+        let sample = i16::from_synth(sample / 4.0);
+        writer.write_sample(sample)?;
+        writer.write_sample(sample)?;
+    }
+    Ok(())
+}
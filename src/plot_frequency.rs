@@ -1,6 +1,8 @@
 use std::path::PathBuf;
 
-use crate::pythagorean_chords::{sine_wave, AMPLITUDE, SAMPLE_RATE};
+use crate::context::AudioContext;
+use crate::pythagorean_chords::{sine_wave, AMPLITUDE};
+use crate::sample::{read_wav, Sample};
 use itertools::Itertools;
 use plotters::prelude::*;
 use rand::SeedableRng;
@@ -170,11 +172,11 @@ fn test_fft_shift() {
     );
 }
 
-pub fn fft_of(buffer: &Vec<f32>) -> Vec<Complex<f32>> {
+pub fn fft_of<S: Sample>(buffer: &Vec<S>) -> Vec<Complex<f32>> {
     let mut sine = buffer
         .iter()
         .cloned()
-        .map(|v| Complex { re: v, im: 0.0f32 })
+        .map(|v| Complex { re: v.to_f64() as f32, im: 0.0f32 })
         .collect::<Vec<_>>();
     let mut planner = FftPlanner::new();
     let fft = planner.plan_fft_forward(sine.len());
@@ -182,7 +184,7 @@ pub fn fft_of(buffer: &Vec<f32>) -> Vec<Complex<f32>> {
     fft_shift(sine)
 }
 
-fn continous_fft_of(buffer: &Vec<f32>, window: usize) -> Vec<(f32, f32, f32)> {
+fn continous_fft_of<S: Sample>(buffer: &Vec<S>, window: usize, context: AudioContext) -> Vec<(f32, f32, f32)> {
     (0..buffer.len())
         .map(|index| {
             buffer[(index.checked_sub(window).unwrap_or(0)..index)]
@@ -203,7 +205,7 @@ fn continous_fft_of(buffer: &Vec<f32>, window: usize) -> Vec<(f32, f32, f32)> {
                     fft_of(&buffer)
                         .into_iter()
                         .enumerate()
-                        .map(|(freq, c)| (index, ((freq as f32) - (window as f32 / 2.0)) * 44100.0 / window as f32, (c.re.powi(2) + c.im.powi(2)).sqrt()))
+                        .map(|(freq, c)| (index, ((freq as f32) - (window as f32 / 2.0)) * context.rate() / window as f32, (c.re.powi(2) + c.im.powi(2)).sqrt()))
                         .collect::<Vec<_>>(),
                 )
             }
@@ -212,11 +214,10 @@ fn continous_fft_of(buffer: &Vec<f32>, window: usize) -> Vec<(f32, f32, f32)> {
         .collect()
 }
 
-fn wav_as_f32<T: AsRef<std::path::Path>>(path: &T) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
-    let mut reader = hound::WavReader::open(path)?;
-    let samples = reader.samples::<i32>().collect::<Result<Vec<i32>, _>>()?;
-    let samples = samples.into_iter().map(|v| v as f32).step_by(2).collect();
-    Ok(samples)
+fn wav_as_f32<T: AsRef<std::path::Path>>(path: &T) -> Result<(Vec<f32>, AudioContext), Box<dyn std::error::Error>> {
+    let (samples, spec) = read_wav::<f32, _>(path)?;
+    let samples = samples.into_iter().step_by(spec.channels as usize).collect();
+    Ok((samples, AudioContext::from_spec(&spec)))
 }
 
 pub fn run() -> Result<(), Box<dyn std::error::Error>> {
@@ -262,18 +263,16 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
 
     // histogram of frequencies for a sinewave
     open_in_browser(plot_histogram(
-        continous_fft_of(&sine, 128),
+        continous_fft_of(&sine, 128, AudioContext::default()),
         "histogram of continous fft",
     )?);
 
+    let (input, input_context) = wav_as_f32(&std::path::PathBuf::from(crate::lo_pass_filter::INPUT_FILE))?;
     open_in_browser(plot_histogram(
-        continous_fft_of(
-            &wav_as_f32(&std::path::PathBuf::from(crate::lo_pass_filter::INPUT_FILE))?,
-            2048,
-        ),
+        continous_fft_of(&input, 2048, input_context),
         "histogram of continous fft for actual WAV audio",
     )?);
-    let niedzwiedz_substance =
+    let (niedzwiedz_substance, context) =
         wav_as_f32(&std::path::PathBuf::from("data/niedzwiedz-substance.wav"))?;
     plot_display(
         niedzwiedz_substance.clone(),
@@ -284,27 +283,25 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
     let buffer_size = 4096;
     let task_name = format!("FFT WIDTH - {}", buffer_size);
     open_in_browser(plot_histogram(
-        continous_fft_of(&niedzwiedz_substance, buffer_size),
+        continous_fft_of(&niedzwiedz_substance, buffer_size, context),
         &task_name,
     )?);
 
     let task_name = format!("LO PASS - {}", 1);
     open_in_browser(plot_histogram(
         continous_fft_of(
-            &LoPassFilter::new(niedzwiedz_substance.clone().into_iter().map(|v| v as i32), 1)
-                .map(|v| v as f32)
-                .collect(),
+            &LoPassFilter::new(niedzwiedz_substance.clone().into_iter(), 1).collect::<Vec<_>>(),
             buffer_size,
+            context,
         ),
         &task_name,
     )?);
     let task_name = format!("LO PASS - {}", 2);
     open_in_browser(plot_histogram(
         continous_fft_of(
-            &LoPassFilter::new(niedzwiedz_substance.into_iter().map(|v| v as i32), 2)
-                .map(|v| v as f32)
-                .collect(),
+            &LoPassFilter::new(niedzwiedz_substance.into_iter(), 2).collect::<Vec<_>>(),
             buffer_size,
+            context,
         ),
         &task_name,
     )?);
@@ -0,0 +1,201 @@
+use crate::context::AudioContext;
+use crate::noise::white_noise;
+use crate::oscillators::Instrument;
+use crate::pythagorean_chords::{make_barka, AMPLITUDE};
+use crate::tuning::{Edo, Pythagorean, A4};
+
+const OUTPUT_FILE: &str = "./output/pluck_barka.wav";
+
+// first order allpass, used both for dispersion and for the fractional part of the delay
+struct Allpass {
+    coefficient: f32,
+    previous_input: f32,
+    previous_output: f32,
+}
+
+impl Allpass {
+    fn new(coefficient: f32) -> Self {
+        Self {
+            coefficient,
+            previous_input: 0.0,
+            previous_output: 0.0,
+        }
+    }
+
+    // phase delay in samples at `omega` radians per sample
+    fn delay(coefficient: f32, omega: f32) -> f32 {
+        let numerator = (-omega.sin()).atan2(coefficient + omega.cos());
+        let denominator = (-coefficient * omega.sin()).atan2(1.0 + coefficient * omega.cos());
+        (denominator - numerator) / omega
+    }
+
+    // coefficient giving `delay` samples at `omega`, delay shrinks as the coefficient grows
+    fn with_delay(delay: f32, omega: f32) -> Self {
+        let (mut low, mut high) = (-0.999f32, 0.999f32);
+        for _ in 0..32 {
+            let middle = (low + high) / 2.0;
+            if Self::delay(middle, omega) > delay {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+        Self::new((low + high) / 2.0)
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let output = self.coefficient * input + self.previous_input
+            - self.coefficient * self.previous_output;
+        self.previous_input = input;
+        self.previous_output = output;
+        output
+    }
+}
+
+// Karplus-Strong string: a noise burst circulating in a delay line one period long
+#[derive(Debug, Clone, PartialEq)]
+pub struct PluckedString {
+    pub decay: f32,         // gain per trip around the loop, 0.0 - 1.0
+    pub damping: f32,       // 0.0 bright - 0.5 dull, weight of the loop averaging filter
+    pub pick_position: f32, // 0.0 - 1.0 along the string, 0.5 plucks in the middle
+    pub stretch: f32,       // 0.0 harmonic - 0.9 stiff and inharmonic
+    pub seed: u64,
+}
+
+impl Default for PluckedString {
+    fn default() -> Self {
+        Self {
+            decay: 0.996,
+            damping: 0.5,
+            pick_position: 0.2,
+            stretch: 0.0,
+            seed: 0,
+        }
+    }
+}
+
+impl PluckedString {
+    pub fn pluck(&self, context: AudioContext, freq: f32) -> StringVoice {
+        let damping = self.damping.clamp(0.0, 0.5);
+        let stretch = -self.stretch.clamp(0.0, 0.9); // negative coefficients disperse
+        let period = context.rate() / freq;
+        let omega = 2.0 * std::f32::consts::PI / period;
+        // the loop filters delay the signal too, the delay line and the tuning allpass
+        // make up the rest of the period
+        let averaging =
+            -(-damping * omega.sin()).atan2(1.0 - damping + damping * omega.cos()) / omega;
+        let remaining = period - averaging - Allpass::delay(stretch, omega);
+        let length = ((remaining - 0.1).floor() as usize).max(2);
+        let fraction = remaining - length as f32;
+
+        let noise = white_noise(self.seed)
+            .take(length)
+            .map(|v| v / AMPLITUDE)
+            .collect::<Vec<_>>();
+        // plucking at `pick_position` cancels the harmonics with a node there
+        let pick = ((self.pick_position.clamp(0.0, 1.0) * length as f32) as usize).max(1);
+        let buffer = (0..length)
+            .map(|index| noise[index] - index.checked_sub(pick).map_or(0.0, |i| noise[i]))
+            .collect::<Vec<_>>();
+        // any DC offset would circulate forever
+        let mean = buffer.iter().sum::<f32>() / length as f32;
+        let buffer = buffer.into_iter().map(|v| v - mean).collect();
+
+        StringVoice {
+            buffer,
+            index: 0,
+            decay: self.decay,
+            damping,
+            previous: 0.0,
+            stretch: Allpass::new(stretch),
+            tuning: Allpass::with_delay(fraction, omega),
+        }
+    }
+}
+
+impl Instrument for PluckedString {
+    fn voice(&self, context: AudioContext, freq: f32) -> Box<dyn Iterator<Item = f32>> {
+        Box::new(self.pluck(context, freq))
+    }
+}
+
+pub struct StringVoice {
+    buffer: Vec<f32>,
+    index: usize,
+    decay: f32,
+    damping: f32,
+    previous: f32,
+    stretch: Allpass,
+    tuning: Allpass,
+}
+
+impl Iterator for StringVoice {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let output = self.buffer[self.index];
+        let averaged = (1.0 - self.damping) * output + self.damping * self.previous;
+        self.previous = output;
+        let feedback = self.tuning.process(self.stretch.process(averaged));
+        self.buffer[self.index] = feedback * self.decay;
+        self.index = (self.index + 1) % self.buffer.len();
+        Some(output * AMPLITUDE)
+    }
+}
+
+#[test]
+fn test_pluck_tuning() {
+    // autocorrelation around the expected lag, refined with a parabola
+    let rates = [44100, 48000];
+    let freqs = [110.0, 261.6256, 440.0, 987.7666];
+    for (context, freq) in rates.iter().flat_map(|rate| {
+        freqs
+            .iter()
+            .map(move |freq| (AudioContext::new(*rate), freq))
+    }) {
+        let string = PluckedString {
+            decay: 0.9999,
+            ..Default::default()
+        };
+        let samples = string
+            .pluck(context, *freq)
+            .skip(context.sample_rate as usize) // upper harmonics have died out by now
+            .take(context.sample_rate as usize / 2)
+            .collect::<Vec<_>>();
+        let correlation = |lag: usize| {
+            samples[..samples.len() - lag]
+                .iter()
+                .zip(samples[lag..].iter())
+                .map(|(one, other)| one * other)
+                .sum::<f32>()
+        };
+        let expected = (context.rate() / freq).round() as usize;
+        let lag = (expected - 2..=expected + 2)
+            .max_by(|one, other| correlation(*one).partial_cmp(&correlation(*other)).unwrap())
+            .unwrap();
+        let (before, peak, after) = (correlation(lag - 1), correlation(lag), correlation(lag + 1));
+        let period = lag as f32 + 0.5 * (before - after) / (before - 2.0 * peak + after);
+        let cents = 1200.0 * (context.rate() / period / freq).log2();
+        assert!(
+            cents.abs() < 1.0,
+            "{} Hz at {} Hz is off by {} cents",
+            freq,
+            context.sample_rate,
+            cents
+        );
+    }
+}
+
+pub fn run(context: AudioContext) -> Result<(), Box<dyn std::error::Error>> {
+    println!("10 :: rendering barka on a plucked string");
+    let spec = context.wav_spec(2); // pythagorean on the left, equal temperament on the right
+    let mut writer = hound::WavWriter::create(OUTPUT_FILE, spec)?;
+    let string = PluckedString::default();
+    let barka_pythagorean = make_barka::<i16>(context, &Pythagorean::new(A4 * 0.5), &string)?;
+    let barka_tempered = make_barka::<i16>(context, &Edo::twelve(A4 * 0.5), &string)?;
+    for (pythagorean, equal) in barka_pythagorean.into_iter().zip(barka_tempered) {
+        writer.write_sample(pythagorean)?;
+        writer.write_sample(equal)?;
+    }
+    Ok(())
+}
@@ -1,19 +1,19 @@
 use hound; // WAV codec library
 use itertools::Itertools;
 
-pub const SAMPLE_RATE: u32 = 44100; // 44100 signal samples per second
-pub const AMPLITUDE: f32 = std::i16::MAX as f32 * 0.6; // to avoid distortion
+use crate::context::AudioContext;
+use crate::envelope::Envelope;
+use crate::midi::{Microtuning, MidiFile, MidiNote};
+use crate::oscillators::{BandLimited, Instrument, Waveform};
+use crate::sample::Sample;
+use crate::scale::{Key, Mode};
+use crate::sequencer::Timeline;
+use crate::tuning::{Edo, Pythagorean, Tuning, A4};
 
-const A4: f32 = 440.0;
+pub const AMPLITUDE: f32 = std::i16::MAX as f32 * 0.6; // to avoid distortion
 
-pub fn sine_wave(freq: f32) -> impl Iterator<Item = f32> {
-    Box::new(
-        std::iter::repeat(())
-            .enumerate()
-            .map(|(index, _)| index)
-            .map(|v| v as f32 / SAMPLE_RATE as f32)
-            .map(move |v| (v * freq * 2.0 * std::f32::consts::PI).sin() * AMPLITUDE),
-    )
+pub fn sine_wave(context: AudioContext, freq: f32) -> impl Iterator<Item = f32> {
+    BandLimited::new(context, Waveform::Sine, freq)
 }
 
 fn sum_iters<'a>(
@@ -26,188 +26,174 @@ fn sum_iters<'a>(
     )
 }
 
-fn chord(frequencies: Vec<f32>) -> impl Iterator<Item = f32> {
-    let sines = frequencies.into_iter().map(|freq| sine_wave(freq));
-    let final_wave = sines.fold(Box::new(std::iter::repeat(0.0f32)) as _, sum_iters);
-    Box::new(final_wave)
+// plays every note index of `notes` in `tuning` at once
+pub fn chord(
+    context: AudioContext,
+    tuning: &impl Tuning,
+    notes: Vec<i32>,
+    instrument: &impl Instrument,
+) -> Box<dyn Iterator<Item = f32>> {
+    let waves = notes
+        .into_iter()
+        .map(|note| instrument.voice(context, tuning.frequency(note)));
+    waves.fold(Box::new(std::iter::repeat(0.0f32)) as _, sum_iters)
 }
 
-mod pythagorean {
-    use super::A4;
-    const OFFSETS: [f32; 12] = [
-        1.0 / 1.0,
-        256.0 / 243.0,
-        9.0 / 8.0,
-        32.0 / 27.0,
-        81.0 / 64.0,
-        4.0 / 3.0,
-        // 1024.0/729.0,
-        729.0 / 512.0,
-        3.0 / 2.0,
-        128.0 / 81.0,
-        27.0 / 16.0,
-        16.0 / 9.0,
-        243.0 / 128.0,
-    ];
-
-    pub fn notes() -> Vec<f32> {
-        OFFSETS.iter().map(|offset| A4 * 0.5 * offset).collect()
-    }
+// 6/8 at 100 quarter notes a minute, 0.3 seconds to an eighth note
+pub fn barka_timeline() -> Timeline {
+    Timeline::new(960, 100.0)
+        .and_then(|timeline| timeline.with_meter(0, 6, 8))
+        .expect("6/8 at 100 bpm is a valid timeline")
 }
 
-mod equal_temperament {
-    use super::A4;
-    const OFFSETS: [f32; 12] = [
-        1.0, 1.059463, 1.122462, 1.189207, 1.259921, 1.33484, 1.414214, 1.498307, 1.587401,
-        1.681793, 1.781797, 1.887749,
+// note indices of `tuning` and how many eighth notes each one lasts
+pub fn barka_melody(tuning: &impl Tuning) -> Result<Vec<(i32, usize)>, Box<dyn std::error::Error>> {
+    #[rustfmt::skip]
+    let barka = vec![
+        9, 9, 9, // pan
+        9, 9, 9,
+        9, 8, 9, // kiedyś
+        10, 9, 8, // stanął nad
+        7, 7, 7, // brze-e-giem
+        7, 7, 7,
+        7, 7, 7,
+        8, 8, 9,
+        
+        10, 10, 10,
+        10, 10, 10,
+        10, 10, 10,
+        10, 10, 9,
+        8, 8, 8,
+        8, 8, 8,
+        8, 8, 4,
+        7, 7, 8,
+        
+        9, 9, 9,
+        9, 9, 9,
+        9, 9, 9,
+        10, 10, 8,
+        7, 7, 7,
+        7, 7, 7,
+        7, 7, 7,
+        7, 7, 7,
+        
+        12, 12, 12,
+        12, 12, 12,
+        12, 12, 13,
+        14, 13, 12,
+        11, 11, 11,
+        11, 11, 11,
+        11, 11, 11,
+        10, 10, 9,
+        
+        10, 10, 10,
+        10, 10, 10,
+        10, 10, 11,
+        12, 11, 10,
+        9, 9, 9,
+        9, 9, 9,
+        9, 9, 9,
+        7, 7, 7,
+        
+        12, 12, 12,
+        12, 12, 12,
+        12, 12, 13,
+        14, 13, 12,
+        11, 11, 11,
+        9, 9, 9,
+        9, 9, 9,
+        10, 10, 9,
+        
+        10, 10, 10,
+        10, 10, 10,
+        10, 8, 9,
+        10, 9, 8,
+        7, 7, 7,
+        7, 7, 7,
+        7, 7, 7,
+        7, 7, 7,
     ];
 
-    pub fn notes() -> Vec<f32> {
-        OFFSETS.iter().map(|offset| A4 * 0.5 * offset).collect()
-    }
+    // a major scale on note 0, whatever the tuning
+    let key = Key::new(tuning, 0, &Mode::MAJOR)?;
+    // repeated entries are one longer note
+    Ok(barka
+        .into_iter()
+        .group_by(|v| *v)
+        .into_iter()
+        .map(|(v, group)| (key.degree(v), group.count()))
+        .collect())
 }
 
-pub fn run() -> Result<(), Box<dyn std::error::Error>> {
-    fn make_barka(notes: Vec<f32>) -> Vec<i16> {
-        #[rustfmt::skip]
-        let barka = vec![
-            9, 9, 9, // pan
-            9, 9, 9,
-            9, 8, 9, // kiedyś
-            10, 9, 8, // stanął nad
-            7, 7, 7, // brze-e-giem
-            7, 7, 7,
-            7, 7, 7,
-            8, 8, 9,
-            
-            10, 10, 10,
-            10, 10, 10,
-            10, 10, 10,
-            10, 10, 9,
-            8, 8, 8,
-            8, 8, 8,
-            8, 8, 4,
-            7, 7, 8,
-            
-            9, 9, 9,
-            9, 9, 9,
-            9, 9, 9,
-            10, 10, 8,
-            7, 7, 7,
-            7, 7, 7,
-            7, 7, 7,
-            7, 7, 7,
-            
-            12, 12, 12,
-            12, 12, 12,
-            12, 12, 13,
-            14, 13, 12,
-            11, 11, 11,
-            11, 11, 11,
-            11, 11, 11,
-            10, 10, 9,
-            
-            10, 10, 10,
-            10, 10, 10,
-            10, 10, 11,
-            12, 11, 10,
-            9, 9, 9,
-            9, 9, 9,
-            9, 9, 9,
-            7, 7, 7,
-            
-            12, 12, 12,
-            12, 12, 12,
-            12, 12, 13,
-            14, 13, 12,
-            11, 11, 11,
-            9, 9, 9,
-            9, 9, 9,
-            10, 10, 9,
-            
-            10, 10, 10,
-            10, 10, 10,
-            10, 8, 9,
-            10, 9, 8,
-            7, 7, 7,
-            7, 7, 7,
-            7, 7, 7,
-            7, 7, 7,
-        ];
-
-        let mut song_chords: Vec<Vec<f32>> = vec![];
-        let mut notes = notes.clone();
-        notes.append(
-            &mut notes
-                .iter()
-                .map(|v| v * 2.0)
-                .chain(notes.iter().map(|v| v * 4.0))
-                .chain(notes.iter().map(|v| v * 8.0))
-                .chain(notes.iter().map(|v| v * 16.0))
-                .collect(),
-        );
-
-        let scale = vec![
-            notes[0],
-            notes[2],
-            notes[4],
-            notes[5],
-            notes[7],
-            notes[9],
-            notes[11],
-            notes[12 + 0],
-            notes[12 + 2],
-            notes[12 + 4],
-            notes[12 + 5],
-            notes[12 + 7],
-            notes[12 + 9],
-            notes[12 + 11],
-            notes[2 * 12 + 0],
-            notes[2 * 12 + 2],
-            notes[2 * 12 + 4],
-            notes[2 * 12 + 5],
-            notes[2 * 12 + 7],
-            notes[2 * 12 + 9],
-            notes[2 * 12 + 11],
-            notes[3 * 12 + 0],
-            notes[3 * 12 + 2],
-            notes[3 * 12 + 4],
-            notes[3 * 12 + 5],
-            notes[3 * 12 + 7],
-            notes[3 * 12 + 9],
-            notes[3 * 12 + 11],
-            notes[4 * 12 + 0],
-            notes[4 * 12 + 2],
-            notes[4 * 12 + 4],
-            notes[4 * 12 + 5],
-            notes[4 * 12 + 7],
-            notes[4 * 12 + 9],
-            notes[4 * 12 + 11],
-        ];
-        song_chords.append(&mut barka.into_iter().map(|v| vec![scale[v]]).collect());
-
-        let note_length = 0.3 * SAMPLE_RATE as f32;
-        let song = song_chords.into_iter().map(chord).fold(
+pub fn make_barka<S: Sample>(
+    context: AudioContext,
+    tuning: &impl Tuning,
+    instrument: &impl Instrument,
+) -> Result<Vec<S>, Box<dyn std::error::Error>> {
+    let mut song_chords: Vec<Vec<i32>> = vec![];
+    let barka = barka_melody(tuning)?;
+    song_chords.append(&mut barka.iter().map(|(v, _)| vec![*v]).collect());
+
+    let timeline = barka_timeline();
+    let eighth = timeline.beat_ticks(0);
+    // where every note starts and ends, in ticks
+    let ticks = barka.iter().scan(0, |tick, (_, count)| {
+        let start = *tick;
+        *tick += *count as u64 * eighth;
+        Some((start, *tick))
+    });
+    let envelope = Envelope::adsr(0.01, 0.1, 0.8, 0.05);
+    let release = context.samples(envelope.release_time());
+    let song = song_chords
+        .into_iter()
+        .zip(ticks)
+        .map(|(notes, (start, end))| {
+            let length = timeline.sample(context, end) - timeline.sample(context, start);
+            // the release fades out before the next note starts, no clicks
+            envelope
+                .note(
+                    context,
+                    chord(context, tuning, notes, instrument),
+                    length.saturating_sub(release),
+                )
+                .chain(std::iter::repeat(0.0))
+                .take(length)
+        })
+        .fold(
             Box::new(std::iter::empty::<f32>()) as _,
-            |song: Box<dyn Iterator<Item = f32>>, chunk| {
-                Box::new(song.chain(chunk.take(note_length as usize)))
-                // 2 seconds of each chord
-            },
+            |song: Box<dyn Iterator<Item = f32>>, chunk| Box::new(song.chain(chunk)),
         );
 
-        song.map(|sample| sample as i16).collect()
-    }
+    Ok(song.map(S::from_synth).collect())
+}
 
+pub fn run(context: AudioContext) -> Result<(), Box<dyn std::error::Error>> {
     println!("2 :: generating pythagorean chords");
-    let spec = hound::WavSpec {
-        channels: 2, // mono
-        sample_rate: SAMPLE_RATE,
-        bits_per_sample: 16,
-        sample_format: hound::SampleFormat::Int,
-    };
+    let spec = context.wav_spec(2); // pythagorean on the left, equal temperament on the right
     let mut writer = hound::WavWriter::create("./output/pythagorean_chords.wav", spec)?;
-    let barka_pythagorean = make_barka(pythagorean::notes());
-    let barka_tempered = make_barka(equal_temperament::notes());
+    let pythagorean = Pythagorean::new(A4 * 0.5);
+    let (comma_step, used, twin) = pythagorean.comma();
+    println!(
+        "wolf fifth from step {} to step {}, step {} is {:.4} and not {:.4}",
+        pythagorean.wolf(),
+        (pythagorean.wolf() + 7) % 12,
+        comma_step,
+        used,
+        twin
+    );
+    let melody = barka_melody(&pythagorean)?;
+    let keys = melody.iter().map(|(key, _)| *key).collect::<Vec<_>>();
+    let wolves = pythagorean.wolf_intervals(&keys);
+    if wolves.is_empty() {
+        println!("barka never spans the wolf fifth");
+    } else if let Some(chain) = Pythagorean::avoiding(A4 * 0.5, &keys) {
+        println!(
+            "barka spans the wolf fifth between {:?}, a chain from {} fifths down avoids it",
+            wolves, -chain.lowest
+        );
+    }
+    let barka_pythagorean = make_barka::<i16>(context, &pythagorean, &Waveform::Sine)?;
+    let barka_tempered = make_barka::<i16>(context, &Edo::twelve(A4 * 0.5), &Waveform::Sine)?;
 
     for (pythagorean, equal) in barka_pythagorean
         .into_iter()
@@ -216,5 +202,38 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
         writer.write_sample(pythagorean)?;
         writer.write_sample(equal)?;
     }
+
+    // the pythagorean melody for other synths, bent or retuned away from equal temperament
+    let timeline = barka_timeline();
+    let notes = melody
+        .into_iter()
+        .scan(0, |tick, (key, count)| {
+            let start = timeline.seconds(*tick);
+            *tick += count as u64 * timeline.beat_ticks(0);
+            let note = MidiNote {
+                start: start as f32,
+                length: (timeline.seconds(*tick) - start) as f32,
+                channel: 0,
+                key,
+                velocity: 0.8,
+                program: 73, // flute
+            };
+            Some(note)
+        })
+        .collect::<Vec<_>>();
+    let tempo = timeline.bpm(0) as f32;
+    let meter = timeline.meter(0);
+    let meter = (meter.numerator as u8, meter.denominator as u8);
+    let bent = Microtuning::PitchBend { range: 2 };
+    MidiFile::from_notes(&notes, &pythagorean, bent, tempo, meter)?
+        .save("./output/barka_pitch_bend.mid")?;
+    MidiFile::from_notes(
+        &notes,
+        &pythagorean,
+        Microtuning::TuningStandard,
+        tempo,
+        meter,
+    )?
+    .save("./output/barka_mts.mid")?;
     Ok(())
 }
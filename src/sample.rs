@@ -0,0 +1,307 @@
+use std::io::{Seek, Write};
+
+use crate::context::AudioContext;
+
+// the synth code renders f32 in 16 bit steps, `AMPLITUDE` is 60% of full scale
+const SYNTH_FULL_SCALE: f64 = 32768.0;
+
+// one sample in any of the formats a WAV file can hold
+//
+// integers map full scale to -1.0 - 1.0 by dividing by 2^(bits - 1), so going
+// through `f64` is exact for every format and widening never loses anything
+pub trait Sample: Copy + Default + PartialOrd + std::fmt::Debug + Send + Sync + 'static {
+    type Raw: hound::Sample + Copy;
+    const BITS: u16;
+    const FORMAT: hound::SampleFormat;
+
+    // -1.0 - 1.0 at full scale, floats can go past that
+    fn to_f64(self) -> f64;
+
+    // rounds to the nearest step, integers saturate at full scale
+    fn from_f64(value: f64) -> Self;
+
+    // what hound reads and writes for this format
+    fn to_raw(self) -> Self::Raw;
+    fn from_raw(raw: Self::Raw) -> Self;
+
+    fn convert<S: Sample>(self) -> S {
+        S::from_f64(self.to_f64())
+    }
+
+    fn from_synth(value: f32) -> Self {
+        Self::from_f64(value as f64 / SYNTH_FULL_SCALE)
+    }
+
+    fn to_synth(self) -> f32 {
+        (self.to_f64() * SYNTH_FULL_SCALE) as f32
+    }
+}
+
+fn int_from_f64(value: f64, bits: u16) -> i32 {
+    let scale = (1i64 << (bits - 1)) as f64;
+    (value * scale).round().max(-scale).min(scale - 1.0) as i32
+}
+
+fn int_to_f64(value: i32, bits: u16) -> f64 {
+    value as f64 / (1i64 << (bits - 1)) as f64
+}
+
+impl Sample for i16 {
+    type Raw = i16;
+    const BITS: u16 = 16;
+    const FORMAT: hound::SampleFormat = hound::SampleFormat::Int;
+
+    fn to_f64(self) -> f64 {
+        int_to_f64(self as i32, <Self as Sample>::BITS)
+    }
+
+    fn from_f64(value: f64) -> Self {
+        int_from_f64(value, <Self as Sample>::BITS) as i16
+    }
+
+    fn to_raw(self) -> Self::Raw {
+        self
+    }
+
+    fn from_raw(raw: Self::Raw) -> Self {
+        raw
+    }
+}
+
+// 24 bit integer, stored in the low bits of an i32
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct I24(i32);
+
+impl I24 {
+    pub const MAX: i32 = (1 << 23) - 1;
+    pub const MIN: i32 = -(1 << 23);
+
+    // saturates values that do not fit in 24 bits
+    pub fn new(value: i32) -> Self {
+        Self(value.clamp(Self::MIN, Self::MAX))
+    }
+
+    pub fn get(self) -> i32 {
+        self.0
+    }
+}
+
+impl Sample for I24 {
+    type Raw = i32;
+    const BITS: u16 = 24;
+    const FORMAT: hound::SampleFormat = hound::SampleFormat::Int;
+
+    fn to_f64(self) -> f64 {
+        int_to_f64(self.0, Self::BITS)
+    }
+
+    fn from_f64(value: f64) -> Self {
+        Self(int_from_f64(value, Self::BITS))
+    }
+
+    fn to_raw(self) -> Self::Raw {
+        self.0
+    }
+
+    fn from_raw(raw: Self::Raw) -> Self {
+        Self::new(raw)
+    }
+}
+
+impl Sample for i32 {
+    type Raw = i32;
+    const BITS: u16 = 32;
+    const FORMAT: hound::SampleFormat = hound::SampleFormat::Int;
+
+    fn to_f64(self) -> f64 {
+        int_to_f64(self, <Self as Sample>::BITS)
+    }
+
+    fn from_f64(value: f64) -> Self {
+        int_from_f64(value, <Self as Sample>::BITS)
+    }
+
+    fn to_raw(self) -> Self::Raw {
+        self
+    }
+
+    fn from_raw(raw: Self::Raw) -> Self {
+        raw
+    }
+}
+
+impl Sample for f32 {
+    type Raw = f32;
+    const BITS: u16 = 32;
+    const FORMAT: hound::SampleFormat = hound::SampleFormat::Float;
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn from_f64(value: f64) -> Self {
+        value as f32
+    }
+
+    fn to_raw(self) -> Self::Raw {
+        self
+    }
+
+    fn from_raw(raw: Self::Raw) -> Self {
+        raw
+    }
+}
+
+// WAV files have no 64 bit float samples in hound, these get written as f32
+impl Sample for f64 {
+    type Raw = f32;
+    const BITS: u16 = 32;
+    const FORMAT: hound::SampleFormat = hound::SampleFormat::Float;
+
+    fn to_f64(self) -> f64 {
+        self
+    }
+
+    fn from_f64(value: f64) -> Self {
+        value
+    }
+
+    fn to_raw(self) -> Self::Raw {
+        self as f32
+    }
+
+    fn from_raw(raw: Self::Raw) -> Self {
+        raw as f64
+    }
+}
+
+#[test]
+fn test_sample_conversions() {
+    // widening is lossless
+    for value in [i16::MIN, -1, 0, 1, 12345, i16::MAX].iter() {
+        assert_eq!(value.convert::<I24>().convert::<i16>(), *value);
+        assert_eq!(value.convert::<i32>().convert::<i16>(), *value);
+        assert_eq!(value.convert::<f32>().convert::<i16>(), *value);
+        assert_eq!(value.convert::<I24>().get(), *value as i32 * 256);
+    }
+    assert_eq!(I24::new(I24::MIN).convert::<f32>(), -1.0);
+    assert_eq!(i32::MAX.convert::<f64>().convert::<i32>(), i32::MAX);
+
+    // narrowing rounds and saturates
+    assert_eq!(I24::new(383).convert::<i16>(), 1);
+    assert_eq!(1.5f32.convert::<i16>(), i16::MAX);
+    assert_eq!((-1.5f64).convert::<I24>(), I24::new(I24::MIN));
+    assert_eq!(I24::new(1 << 30), I24::new(I24::MAX));
+}
+
+// reads every channel, interleaved, whatever format the file is in
+pub fn read_wav<S: Sample, P: AsRef<std::path::Path>>(
+    path: P,
+) -> Result<(Vec<S>, hound::WavSpec), Box<dyn std::error::Error>> {
+    let mut reader = hound::WavReader::open(path)?;
+    let spec = reader.spec();
+    let samples = match (spec.sample_format, spec.bits_per_sample) {
+        (hound::SampleFormat::Float, 32) => reader
+            .samples::<f32>()
+            .map(|sample| sample.map(|v| v.convert()))
+            .collect::<Result<Vec<_>, _>>()?,
+        (hound::SampleFormat::Int, bits) if bits > 0 && bits <= 32 => reader
+            .samples::<i32>()
+            .map(|sample| sample.map(|v| S::from_f64(int_to_f64(v, bits))))
+            .collect::<Result<Vec<_>, _>>()?,
+        (format, bits) => {
+            return Err(format!("unsupported WAV format: {} bit {:?}", bits, format).into())
+        }
+    };
+    Ok((samples, spec))
+}
+
+// WAV spec that holds `S` exactly
+pub fn spec_for<S: Sample>(context: AudioContext, channels: u16) -> hound::WavSpec {
+    hound::WavSpec {
+        channels,
+        sample_rate: context.sample_rate,
+        bits_per_sample: S::BITS,
+        sample_format: S::FORMAT,
+    }
+}
+
+// writes any sample into a file of any format, converting on the way
+pub fn write_sample<S: Sample, W: Write + Seek>(
+    writer: &mut hound::WavWriter<W>,
+    spec: hound::WavSpec,
+    sample: S,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match (spec.sample_format, spec.bits_per_sample) {
+        (hound::SampleFormat::Float, 32) => writer.write_sample(sample.convert::<f32>())?,
+        (hound::SampleFormat::Int, bits) if bits > 0 && bits <= 32 => {
+            writer.write_sample(int_from_f64(sample.to_f64(), bits))?
+        }
+        (format, bits) => {
+            return Err(format!("unsupported WAV format: {} bit {:?}", bits, format).into())
+        }
+    }
+    Ok(())
+}
+
+// converts a synth signal, see `Sample::from_synth`
+pub trait IntoSamples: Iterator<Item = f32> + Sized {
+    fn samples<S: Sample>(self) -> std::iter::Map<Self, fn(f32) -> S> {
+        self.map(S::from_synth as fn(f32) -> S)
+    }
+}
+
+impl<I: Iterator<Item = f32>> IntoSamples for I {}
+
+pub fn run(context: AudioContext) -> Result<(), Box<dyn std::error::Error>> {
+    println!("16 :: writing one sine in every sample format");
+    let sine = || crate::pythagorean_chords::sine_wave(context, 440.0).take(context.samples(1.0));
+    let mut writer =
+        hound::WavWriter::create("./output/sample_i16.wav", spec_for::<i16>(context, 1))?;
+    for sample in sine().samples::<i16>() {
+        writer.write_sample(sample.to_raw())?;
+    }
+    writer.finalize()?;
+    let mut writer =
+        hound::WavWriter::create("./output/sample_i24.wav", spec_for::<I24>(context, 1))?;
+    for sample in sine().samples::<I24>() {
+        writer.write_sample(sample.to_raw())?;
+    }
+    writer.finalize()?;
+    let mut writer =
+        hound::WavWriter::create("./output/sample_f32.wav", spec_for::<f32>(context, 1))?;
+    for sample in sine().samples::<f32>() {
+        writer.write_sample(sample.to_raw())?;
+    }
+    writer.finalize()?;
+
+    // and back, every file reads into the same values
+    let (reference, _) = read_wav::<f64, _>("./output/sample_i16.wav")?;
+    for path in ["./output/sample_i24.wav", "./output/sample_f32.wav"].iter() {
+        let (samples, spec) = read_wav::<f64, _>(path)?;
+        let error = samples
+            .iter()
+            .zip(reference.iter())
+            .map(|(one, other)| (one - other).abs())
+            .fold(0.0, f64::max);
+        println!(
+            "{} bit {:?} differs from 16 bit by at most {:.1e}",
+            spec.bits_per_sample, spec.sample_format, error
+        );
+    }
+
+    // the raw 24 bit values, AMPLITUDE keeps the sine under full scale
+    let mut reader = hound::WavReader::open("./output/sample_i24.wav")?;
+    let raw = reader
+        .samples::<i32>()
+        .map(|sample| sample.map(|v| I24::from_raw(v).get()))
+        .collect::<Result<Vec<_>, _>>()?;
+    println!(
+        "24 bit sine spans {} to {}, full scale is {} to {}",
+        raw.iter().min().unwrap_or(&0),
+        raw.iter().max().unwrap_or(&0),
+        I24::MIN,
+        I24::MAX
+    );
+    Ok(())
+}
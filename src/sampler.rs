@@ -0,0 +1,343 @@
+use std::sync::Arc;
+
+use crate::context::AudioContext;
+use crate::fm::Patch;
+use crate::oscillators::{Instrument, Waveform};
+use crate::pythagorean_chords::{make_barka, AMPLITUDE};
+use crate::sample::Sample;
+use crate::tuning::{Edo, Pythagorean, A4};
+use crate::wavetable::read_first_channel;
+
+const OUTPUT_FILE: &str = "./output/sampler_barka.wav";
+const SOURCE_FILE: &str = "./output/sampler_source.wav";
+const VELOCITIES_FILE: &str = "./output/sampler_velocities.wav";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoopMode {
+    Off,
+    // jumps from `end` back to `start`, fading the last `crossfade` samples into the loop start
+    Forward {
+        start: usize,
+        end: usize,
+        crossfade: usize,
+    },
+    PingPong {
+        start: usize,
+        end: usize,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct Zone {
+    pub sample: Arc<Vec<f32>>, // -1.0 - 1.0
+    pub root_key: f32,         // key the sample plays at without repitching
+    pub keys: (f32, f32),      // lowest and highest key of the zone, inclusive
+    pub velocities: (f32, f32),
+    pub looping: LoopMode,
+    pub sample_rate: u32, // the sample was recorded at
+}
+
+impl Zone {
+    pub fn new(sample: Vec<f32>, root_key: f32) -> Self {
+        Self {
+            sample: Arc::new(sample),
+            root_key,
+            keys: (0.0, 127.0),
+            velocities: (0.0, 1.0),
+            looping: LoopMode::Off,
+            sample_rate: AudioContext::default().sample_rate,
+        }
+    }
+
+    pub fn from_wav<T: AsRef<std::path::Path>>(
+        path: T,
+        root_key: f32,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let (sample, spec) = read_first_channel(path)?;
+        if sample.is_empty() {
+            return Err("sample is empty".into());
+        }
+        Ok(Self::new(sample, root_key).sample_rate(spec.sample_rate))
+    }
+
+    pub fn keys(mut self, low: f32, high: f32) -> Self {
+        self.keys = (low, high);
+        self
+    }
+
+    pub fn velocities(mut self, low: f32, high: f32) -> Self {
+        self.velocities = (low, high);
+        self
+    }
+
+    // the loop has to start before it ends, and end within the sample
+    pub fn looping(mut self, looping: LoopMode) -> Result<Self, Box<dyn std::error::Error>> {
+        match looping {
+            LoopMode::Forward { start, end, .. } | LoopMode::PingPong { start, end }
+                if start >= end || end > self.sample.len() =>
+            {
+                return Err(format!(
+                    "loop {} - {} doesn't fit a sample of {} samples",
+                    start,
+                    end,
+                    self.sample.len()
+                )
+                .into())
+            }
+            _ => {}
+        }
+        self.looping = looping;
+        Ok(self)
+    }
+
+    pub fn sample_rate(mut self, sample_rate: u32) -> Self {
+        self.sample_rate = sample_rate;
+        self
+    }
+
+    fn contains(&self, key: f32, velocity: f32) -> bool {
+        key >= self.keys.0
+            && key <= self.keys.1
+            && velocity >= self.velocities.0
+            && velocity <= self.velocities.1
+    }
+}
+
+// fractional key number, 69 is A4 at 440 Hz
+pub fn key_of(freq: f32) -> f32 {
+    69.0 + 12.0 * (freq / A4).log2()
+}
+
+// 4 point, 3rd order Hermite interpolation between `y1` and `y2`
+fn hermite(x: f32, y0: f32, y1: f32, y2: f32, y3: f32) -> f32 {
+    let c1 = 0.5 * (y2 - y0);
+    let c2 = y0 - 2.5 * y1 + 2.0 * y2 - 0.5 * y3;
+    let c3 = 0.5 * (y3 - y0) + 1.5 * (y1 - y2);
+    ((c3 * x + c2) * x + c1) * x + y1
+}
+
+#[test]
+fn test_hermite() {
+    assert_eq!(hermite(0.0, 0.0, 1.0, 2.0, 3.0), 1.0);
+    assert_eq!(hermite(0.5, 0.0, 1.0, 2.0, 3.0), 1.5);
+    assert_eq!(hermite(1.0, 5.0, 1.0, -2.0, 3.0), -2.0);
+}
+
+#[test]
+fn test_loop_points() {
+    let zone = Zone::new(vec![0.5; 100], 60.0);
+    let forward = |start, end| LoopMode::Forward {
+        start,
+        end,
+        crossfade: 0,
+    };
+    assert!(zone.clone().looping(forward(80, 40)).is_err());
+    assert!(zone.clone().looping(forward(10, 200)).is_err());
+    assert!(zone.clone().looping(forward(10, 100)).is_ok());
+
+    // a loop past the end of the sample set by hand plays once and stops
+    let mut broken = zone;
+    broken.looping = LoopMode::PingPong {
+        start: 150,
+        end: 200,
+    };
+    let sampler = Sampler::new(vec![broken]);
+    let voice = sampler.play(AudioContext::default(), 261.63, 1.0);
+    assert!(voice.take(1000).count() < 1000);
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Sampler {
+    pub zones: Vec<Zone>,
+}
+
+impl Sampler {
+    pub fn new(zones: Vec<Zone>) -> Self {
+        Self { zones }
+    }
+
+    // first zone that covers the key and velocity, the nearest root key otherwise
+    fn zone(&self, key: f32, velocity: f32) -> Option<&Zone> {
+        self.zones
+            .iter()
+            .find(|zone| zone.contains(key, velocity))
+            .or_else(|| {
+                self.zones.iter().min_by(|one, other| {
+                    let distance = |zone: &Zone| (zone.root_key - key).abs();
+                    distance(one)
+                        .partial_cmp(&distance(other))
+                        .unwrap_or(std::cmp::Ordering::Equal)
+                })
+            })
+    }
+
+    pub fn play(&self, context: AudioContext, freq: f32, velocity: f32) -> SamplerVoice {
+        let key = key_of(freq);
+        let zone = self.zone(key, velocity).cloned();
+        let rate = zone
+            .as_ref()
+            .map(|zone| {
+                let resample = zone.sample_rate as f32 / context.rate();
+                2.0f32.powf((key - zone.root_key) / 12.0) * resample
+            })
+            .unwrap_or(1.0);
+        SamplerVoice {
+            zone,
+            position: 0.0,
+            rate,
+            direction: 1.0,
+            gain: velocity,
+        }
+    }
+}
+
+impl Instrument for Sampler {
+    fn voice(&self, context: AudioContext, freq: f32) -> Box<dyn Iterator<Item = f32>> {
+        Box::new(self.play(context, freq, 1.0))
+    }
+}
+
+pub struct SamplerVoice {
+    zone: Option<Zone>,
+    position: f32,
+    rate: f32,
+    direction: f32, // -1.0 while a ping-pong loop plays backwards
+    gain: f32,
+}
+
+impl SamplerVoice {
+    fn read(sample: &[f32], position: f32) -> f32 {
+        let index = position.floor() as isize;
+        let at = |offset: isize| {
+            let index = (index + offset).max(0) as usize;
+            sample.get(index).cloned().unwrap_or(0.0)
+        };
+        hermite(position - position.floor(), at(-1), at(0), at(1), at(2))
+    }
+}
+
+impl Iterator for SamplerVoice {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let zone = self.zone.as_ref()?;
+        let sample = &zone.sample;
+        let mut value = Self::read(sample, self.position);
+        // loop points set on the field directly can still be wrong, play those once through
+        let looping = match zone.looping {
+            LoopMode::Forward { start, end, .. } | LoopMode::PingPong { start, end }
+                if start >= end.min(sample.len()) =>
+            {
+                LoopMode::Off
+            }
+            looping => looping,
+        };
+        match looping {
+            LoopMode::Off => {
+                if self.position >= sample.len() as f32 {
+                    return None;
+                }
+                self.position += self.rate;
+            }
+            LoopMode::Forward {
+                start,
+                end,
+                crossfade,
+            } => {
+                let (start, end) = (start as f32, end.min(sample.len()) as f32);
+                let crossfade = (crossfade as f32).min(end - start).min(start);
+                let fade_start = end - crossfade;
+                if crossfade > 0.0 && self.position >= fade_start {
+                    // the tail fades out while the audio before the loop start fades in
+                    let fade = (self.position - fade_start) / crossfade;
+                    let incoming = Self::read(sample, self.position - (end - start));
+                    value = value * (1.0 - fade).sqrt() + incoming * fade.sqrt();
+                }
+                self.position += self.rate;
+                if self.position >= end {
+                    self.position -= end - start;
+                }
+            }
+            LoopMode::PingPong { start, end } => {
+                let (start, end) = (start as f32, end.min(sample.len()) as f32);
+                self.position += self.rate * self.direction;
+                if self.position >= end && self.direction > 0.0 {
+                    self.position = end - (self.position - end);
+                    self.direction = -1.0;
+                } else if self.position <= start && self.direction < 0.0 {
+                    self.position = start + (start - self.position);
+                    self.direction = 1.0;
+                }
+            }
+        }
+        Some(value * self.gain * AMPLITUDE)
+    }
+}
+
+pub fn run(context: AudioContext) -> Result<(), Box<dyn std::error::Error>> {
+    println!("14 :: rendering barka on a sampler");
+    // record a couple of FM notes to use as samples
+    let root_keys = [57.0, 69.0]; // A3, A4
+    let sources = root_keys
+        .iter()
+        .map(|root_key| {
+            let freq = A4 * 2.0f32.powf((root_key - 69.0) / 12.0);
+            Patch::electric_piano()
+                .voice(context, freq)
+                .take(context.samples(1.0))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let mut writer = hound::WavWriter::create(SOURCE_FILE, context.wav_spec(1))?; // mono
+    for sample in sources[0].iter() {
+        writer.write_sample(i16::from_synth(*sample))?;
+    }
+    writer.finalize()?;
+
+    let low = Zone::from_wav(SOURCE_FILE, root_keys[0])?
+        .keys(0.0, 63.0)
+        .looping(LoopMode::Forward {
+            start: context.samples(0.25),
+            end: context.samples(0.75),
+            crossfade: context.samples(0.125),
+        })?;
+    let high = Zone::new(
+        sources[1].iter().map(|v| f32::from_synth(*v)).collect(),
+        root_keys[1],
+    )
+    .keys(64.0, 127.0)
+    .looping(LoopMode::PingPong {
+        start: context.samples(0.25),
+        end: context.samples(0.5),
+    })?
+    .sample_rate(context.sample_rate);
+    let sampler = Sampler::new(vec![low.clone(), high]);
+
+    let spec = context.wav_spec(2); // pythagorean on the left, equal temperament on the right
+    let mut writer = hound::WavWriter::create(OUTPUT_FILE, spec)?;
+    let barka_pythagorean = make_barka::<i16>(context, &Pythagorean::new(A4 * 0.5), &sampler)?;
+    let barka_tempered = make_barka::<i16>(context, &Edo::twelve(A4 * 0.5), &sampler)?;
+    for (pythagorean, equal) in barka_pythagorean.into_iter().zip(barka_tempered) {
+        writer.write_sample(pythagorean)?;
+        writer.write_sample(equal)?;
+    }
+
+    // soft notes play a triangle, hard ones the electric piano
+    let triangle = Waveform::Triangle
+        .voice(context, 220.0)
+        .take(context.samples(1.0))
+        .map(f32::from_synth)
+        .collect();
+    let soft = Zone::new(triangle, root_keys[0])
+        .velocities(0.0, 0.5)
+        .sample_rate(context.sample_rate);
+    let layers = Sampler::new(vec![soft, low.keys(0.0, 127.0).velocities(0.5, 1.0)]);
+    let mut writer = hound::WavWriter::create(VELOCITIES_FILE, context.wav_spec(1))?; // mono
+    for velocity in [0.25, 0.5, 0.75, 1.0].iter() {
+        let note = layers.play(context, 220.0, *velocity);
+        for sample in note.take(context.samples(0.5)) {
+            writer.write_sample(i16::from_synth(sample))?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,519 @@
+use std::path::Path;
+
+use crate::context::AudioContext;
+use crate::oscillators::Waveform;
+use crate::pythagorean_chords::make_barka;
+use crate::tuning::{Meantone, Ratios, Tuning, A4};
+
+// Scala scale (.scl) and keyboard mapping (.kbm) files,
+// see http://www.huygens-fokker.org/scala/scl_format.html
+
+const OUTPUT_FILE: &str = "./output/scala.wav";
+
+// one line of a .scl file, cents have a `.` in them, everything else is a ratio
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pitch {
+    Cents(f64),
+    Ratio(u64, u64),
+}
+
+impl Pitch {
+    // exact ratios of small whole numbers stay ratios, everything else becomes cents
+    pub fn from_ratio(ratio: f64) -> Self {
+        match as_fraction(ratio) {
+            Some((numerator, denominator)) => Pitch::Ratio(numerator, denominator),
+            None => Pitch::Cents(1200.0 * ratio.log2()),
+        }
+    }
+
+    pub fn ratio(self) -> f64 {
+        match self {
+            Pitch::Cents(cents) => 2.0f64.powf(cents / 1200.0),
+            Pitch::Ratio(numerator, denominator) => numerator as f64 / denominator as f64,
+        }
+    }
+
+    fn parse(text: &str, line: usize) -> Result<Self, Box<dyn std::error::Error>> {
+        let invalid = || -> Box<dyn std::error::Error> {
+            format!(
+                "line {}: `{}` is not a pitch, expected cents like `701.955` or a ratio like `3/2`",
+                line, text
+            )
+            .into()
+        };
+        // anything after the value is a label
+        let value = text.split_whitespace().next().ok_or_else(invalid)?;
+        if value.contains('.') {
+            return value.parse().map(Pitch::Cents).map_err(|_| invalid());
+        }
+        let (numerator, denominator) = match value.find('/') {
+            Some(split) => (&value[..split], &value[split + 1..]),
+            None => (value, "1"),
+        };
+        match (numerator.parse::<u64>(), denominator.parse::<u64>()) {
+            (Ok(numerator), Ok(denominator)) if numerator > 0 && denominator > 0 => {
+                Ok(Pitch::Ratio(numerator, denominator))
+            }
+            _ => Err(invalid()),
+        }
+    }
+
+    fn to_text(self) -> String {
+        match self {
+            Pitch::Cents(cents) => format!("{:.6}", cents),
+            Pitch::Ratio(numerator, denominator) => format!("{}/{}", numerator, denominator),
+        }
+    }
+}
+
+// continued fraction expansion, `None` when no ratio with a small denominator fits
+fn as_fraction(value: f64) -> Option<(u64, u64)> {
+    let (mut numerator, mut denominator) = (1u64, 0u64);
+    let (mut previous_numerator, mut previous_denominator) = (0u64, 1u64);
+    let mut rest = value;
+    for _ in 0..32 {
+        let whole = rest.floor();
+        if whole > 1e9 {
+            return None;
+        }
+        let next_numerator = whole as u64 * numerator + previous_numerator;
+        let next_denominator = whole as u64 * denominator + previous_denominator;
+        previous_numerator = numerator;
+        previous_denominator = denominator;
+        numerator = next_numerator;
+        denominator = next_denominator;
+        if denominator > 1 << 20 {
+            return None;
+        }
+        if (numerator as f64 / denominator as f64 - value).abs() < value * 1e-15 {
+            return Some((numerator, denominator));
+        }
+        rest = 1.0 / (rest - whole);
+    }
+    None
+}
+
+// lines that are not comments, with their line numbers
+fn content_lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.starts_with('!'))
+}
+
+// the pitches of a scale above 1/1, the last one is the period it repeats at
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scale {
+    pub description: String,
+    pub pitches: Vec<Pitch>,
+}
+
+impl Scale {
+    pub fn new(description: &str, pitches: Vec<Pitch>) -> Result<Self, Box<dyn std::error::Error>> {
+        if pitches.is_empty() {
+            return Err("a scale needs at least one pitch, the last one is its period".into());
+        }
+        Ok(Self {
+            description: description.to_string(),
+            pitches,
+        })
+    }
+
+    pub fn from_tuning(tuning: &impl Tuning) -> Self {
+        let pitches = (1..tuning.steps())
+            .map(|step| tuning.ratio(step))
+            .chain(std::iter::once(tuning.period()))
+            .map(Pitch::from_ratio)
+            .collect();
+        Self {
+            description: tuning.name(),
+            pitches,
+        }
+    }
+
+    pub fn period(&self) -> f64 {
+        self.pitches[self.pitches.len() - 1].ratio()
+    }
+
+    // ratio of any degree above 1/1, negative degrees go below it
+    pub fn ratio(&self, degree: i32) -> f64 {
+        let steps = self.pitches.len() as i32;
+        let (period, step) = (degree.div_euclid(steps), degree.rem_euclid(steps));
+        let ratio = match step {
+            0 => 1.0,
+            step => self.pitches[step as usize - 1].ratio(),
+        };
+        ratio * self.period().powi(period)
+    }
+
+    // the scale as a tuning with degree 0 at `base`
+    pub fn tuning(&self, base: f32) -> Result<Ratios, Box<dyn std::error::Error>> {
+        let ratios = std::iter::once(1.0)
+            .chain(
+                self.pitches[..self.pitches.len() - 1]
+                    .iter()
+                    .map(|p| p.ratio()),
+            )
+            .collect();
+        Ratios::new(&self.description, ratios, self.period(), base)
+            .map_err(|error| format!("scale `{}`: {}", self.description, error).into())
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut lines = vec![
+            "!".to_string(),
+            self.description.clone(),
+            format!(" {}", self.pitches.len()),
+            "!".to_string(),
+        ];
+        lines.extend(
+            self.pitches
+                .iter()
+                .map(|pitch| format!(" {}", pitch.to_text())),
+        );
+        lines.join("\n") + "\n"
+    }
+
+    pub fn from_text(text: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut lines = content_lines(text);
+        // the description may be an empty line, the count and pitches may not
+        let description = match lines.next() {
+            Some((_, line)) => line.to_string(),
+            None => return Err("expected a description line".into()),
+        };
+        let mut lines = lines.filter(|(_, line)| !line.is_empty());
+        let count = match lines.next() {
+            Some((line_number, line)) => line
+                .split_whitespace()
+                .next()
+                .and_then(|count| count.parse::<usize>().ok())
+                .ok_or_else(|| format!("line {}: `{}` is not a note count", line_number, line))?,
+            None => return Err("expected a note count after the description".into()),
+        };
+        let mut pitches = vec![];
+        let mut last_line = 0;
+        for (line_number, line) in lines {
+            if pitches.len() == count {
+                return Err(format!(
+                    "line {}: found more than the {} pitches the scale declares",
+                    line_number, count
+                )
+                .into());
+            }
+            pitches.push(Pitch::parse(line, line_number)?);
+            last_line = line_number;
+        }
+        if pitches.len() < count {
+            return Err(format!(
+                "line {}: file ends after {} of {} pitches",
+                last_line,
+                pitches.len(),
+                count
+            )
+            .into());
+        }
+        Self::new(&description, pitches)
+    }
+
+    pub fn save<T: AsRef<Path>>(&self, path: T) -> Result<(), Box<dyn std::error::Error>> {
+        std::fs::write(path, self.to_text())?;
+        Ok(())
+    }
+
+    pub fn load<T: AsRef<Path>>(path: T) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::from_text(&std::fs::read_to_string(path)?)
+    }
+}
+
+// first value of the next line of a .kbm header
+fn next_number<'a>(
+    lines: &mut impl Iterator<Item = (usize, &'a str)>,
+    what: &str,
+) -> Result<(usize, f64), Box<dyn std::error::Error>> {
+    let (line_number, line) = lines
+        .next()
+        .ok_or_else(|| format!("file ends before the {}", what))?;
+    let value = line.split_whitespace().next().unwrap_or("");
+    match value.parse::<f64>() {
+        Ok(number) => Ok((line_number, number)),
+        Err(_) => Err(format!("line {}: `{}` is not a valid {}", line_number, value, what).into()),
+    }
+}
+
+fn next_whole<'a>(
+    lines: &mut impl Iterator<Item = (usize, &'a str)>,
+    what: &str,
+) -> Result<i32, Box<dyn std::error::Error>> {
+    let (line_number, value) = next_number(lines, what)?;
+    if value.fract() != 0.0 || value < 0.0 {
+        return Err(format!("line {}: {} has to be a whole number", line_number, what).into());
+    }
+    Ok(value as i32)
+}
+
+fn next_key<'a>(
+    lines: &mut impl Iterator<Item = (usize, &'a str)>,
+    what: &str,
+) -> Result<i32, Box<dyn std::error::Error>> {
+    let (line_number, value) = next_number(lines, what)?;
+    if value.fract() != 0.0 || !(0.0..=127.0).contains(&value) {
+        return Err(format!(
+            "line {}: {} has to be a MIDI key 0 - 127",
+            line_number, what
+        )
+        .into());
+    }
+    Ok(value as i32)
+}
+
+// which MIDI key plays which scale degree and how the whole thing is anchored
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyboardMapping {
+    pub first: i32,
+    pub last: i32,
+    // key that plays degree `map[0]`
+    pub middle: i32,
+    pub reference: i32,
+    pub frequency: f64,
+    // scale degree the map repeats at, ignored when the map is linear
+    pub octave_degree: i32,
+    // one entry per key from `middle` on, `None` leaves a key silent,
+    // an empty map plays one degree per key
+    pub map: Vec<Option<i32>>,
+}
+
+impl Default for KeyboardMapping {
+    fn default() -> Self {
+        Self::linear(60, 69, A4 as f64)
+    }
+}
+
+impl KeyboardMapping {
+    pub fn linear(middle: i32, reference: i32, frequency: f64) -> Self {
+        Self {
+            first: 0,
+            last: 127,
+            middle,
+            reference,
+            frequency,
+            octave_degree: 0,
+            map: vec![],
+        }
+    }
+
+    fn mapped_degree(&self, key: i32) -> Option<i32> {
+        if self.map.is_empty() {
+            return Some(key - self.middle);
+        }
+        let size = self.map.len() as i32;
+        let offset = key - self.middle;
+        let (period, index) = (offset.div_euclid(size), offset.rem_euclid(size));
+        self.map[index as usize].map(|degree| degree + period * self.octave_degree)
+    }
+
+    // scale degree `key` plays, `None` for keys that are not retuned or unmapped
+    pub fn degree(&self, key: i32) -> Option<i32> {
+        if key < self.first || key > self.last {
+            return None;
+        }
+        self.mapped_degree(key)
+    }
+
+    // frequency of scale degree 0, so that `reference` sounds at `frequency`
+    pub fn base(&self, scale: &Scale) -> Result<f64, Box<dyn std::error::Error>> {
+        let degree = self
+            .mapped_degree(self.reference)
+            .ok_or_else(|| format!("reference key {} is not mapped", self.reference))?;
+        Ok(self.frequency / scale.ratio(degree))
+    }
+
+    pub fn frequency(
+        &self,
+        scale: &Scale,
+        key: i32,
+    ) -> Result<Option<f32>, Box<dyn std::error::Error>> {
+        let base = self.base(scale)?;
+        Ok(self
+            .degree(key)
+            .map(|degree| (base * scale.ratio(degree)) as f32))
+    }
+
+    // every MIDI key, the way a synth would retune them
+    pub fn frequencies(
+        &self,
+        scale: &Scale,
+    ) -> Result<Vec<Option<f32>>, Box<dyn std::error::Error>> {
+        (0..128).map(|key| self.frequency(scale, key)).collect()
+    }
+
+    // `scale` as a tuning with note 0 on scale degree 0,
+    // keys the map leaves out or reorders only show up in `frequencies`
+    pub fn tuning(&self, scale: &Scale) -> Result<Ratios, Box<dyn std::error::Error>> {
+        scale.tuning(self.base(scale)? as f32)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut lines = vec![
+            "! map size".to_string(),
+            self.map.len().to_string(),
+            "! first MIDI note to retune".to_string(),
+            self.first.to_string(),
+            "! last MIDI note to retune".to_string(),
+            self.last.to_string(),
+            "! middle note, where the first map entry is played".to_string(),
+            self.middle.to_string(),
+            "! reference note".to_string(),
+            self.reference.to_string(),
+            "! reference frequency".to_string(),
+            format!("{:.6}", self.frequency),
+            "! scale degree of the formal octave".to_string(),
+            self.octave_degree.to_string(),
+            "! mapping".to_string(),
+        ];
+        lines.extend(self.map.iter().map(|degree| match degree {
+            Some(degree) => degree.to_string(),
+            None => "x".to_string(),
+        }));
+        lines.join("\n") + "\n"
+    }
+
+    pub fn from_text(text: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut lines = content_lines(text).filter(|(_, line)| !line.is_empty());
+        let size = next_whole(&mut lines, "map size")? as usize;
+        let first = next_key(&mut lines, "first note")?;
+        let last = next_key(&mut lines, "last note")?;
+        let middle = next_key(&mut lines, "middle note")?;
+        let reference = next_key(&mut lines, "reference note")?;
+        let (line_number, frequency) = next_number(&mut lines, "reference frequency")?;
+        if frequency <= 0.0 {
+            return Err(format!(
+                "line {}: reference frequency has to be above 0",
+                line_number
+            )
+            .into());
+        }
+        let octave_degree = next_whole(&mut lines, "octave degree")?;
+        let mut map = vec![];
+        for (line_number, line) in lines {
+            if map.len() == size {
+                return Err(format!(
+                    "line {}: found more than the {} keys the map declares",
+                    line_number, size
+                )
+                .into());
+            }
+            let value = line.split_whitespace().next().unwrap_or("");
+            map.push(match value {
+                "x" | "X" => None,
+                degree => Some(degree.parse::<i32>().map_err(|_| {
+                    format!(
+                        "line {}: `{}` is not a scale degree or `x`",
+                        line_number, degree
+                    )
+                })?),
+            });
+        }
+        // short maps leave the remaining keys unmapped
+        map.resize(size, None);
+        if first > last {
+            return Err(format!("first note {} is above last note {}", first, last).into());
+        }
+        Ok(Self {
+            first,
+            last,
+            middle,
+            reference,
+            frequency,
+            octave_degree,
+            map,
+        })
+    }
+
+    pub fn save<T: AsRef<Path>>(&self, path: T) -> Result<(), Box<dyn std::error::Error>> {
+        std::fs::write(path, self.to_text())?;
+        Ok(())
+    }
+
+    pub fn load<T: AsRef<Path>>(path: T) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::from_text(&std::fs::read_to_string(path)?)
+    }
+}
+
+#[test]
+fn test_scala() {
+    use crate::tuning::{Edo, Pythagorean};
+
+    let pythagorean = Pythagorean::new(220.0);
+    let scale = Scale::from_text(&Scale::from_tuning(&pythagorean).to_text()).unwrap();
+    assert_eq!(scale.pitches[5], Pitch::Ratio(729, 512));
+    assert_eq!(scale.pitches[11], Pitch::Ratio(2, 1));
+    let tuning = scale.tuning(220.0).unwrap();
+    assert_eq!(tuning.notes(36), pythagorean.notes(36));
+    match Scale::from_tuning(&Edo::twelve(220.0)).pitches[0] {
+        Pitch::Cents(cents) => assert!((cents - 100.0).abs() < 1e-9),
+        other => panic!("{:?} is not in cents", other),
+    }
+
+    let text = "! comment\n\n 3\n!\n 386.3137 third\n 3/2\n 2\n";
+    let scale = Scale::from_text(text).unwrap();
+    assert_eq!(scale.description, "");
+    assert!((scale.ratio(1) - 1.25).abs() < 1e-6);
+    assert_eq!(scale.ratio(-1), 0.75);
+
+    let error = |text: &str| Scale::from_text(text).unwrap_err().to_string();
+    assert!(error("test\n 2\n 3/2\n 2.0.1\n").starts_with("line 4:"));
+    assert!(error("test\n 3\n 3/2\n 2\n").starts_with("line 4: file ends after 2 of 3"));
+    assert!(error("test\n 2\n 0/2\n 2\n").starts_with("line 3:"));
+    assert!(error("test\n 1\n 3/2\n 2\n").starts_with("line 4:"));
+    assert!(Scale::from_text("test\n 2\n 3/2\n 4/3\n")
+        .unwrap()
+        .tuning(100.0)
+        .is_err());
+
+    // white keys only, C4 on degree 0, A4 at 440
+    let mapping = KeyboardMapping::from_text(
+        "12\n0\n127\n60\n69\n440.0\n7\n0\nx\n1\nx\n2\n3\nx\n4\nx\n5\nx\n6\n",
+    )
+    .unwrap();
+    let major = Scale::from_text("major\n7\n9/8\n5/4\n4/3\n3/2\n5/3\n15/8\n2/1\n").unwrap();
+    assert_eq!(mapping.frequency(&major, 69).unwrap(), Some(440.0));
+    assert_eq!(mapping.frequency(&major, 72).unwrap(), Some(528.0));
+    assert_eq!(mapping.frequency(&major, 61).unwrap(), None);
+    assert_eq!(mapping.frequency(&major, 55).unwrap(), Some(198.0));
+    assert_eq!(
+        KeyboardMapping::from_text(&mapping.to_text()).unwrap(),
+        mapping
+    );
+    let error = KeyboardMapping::from_text("12\n0\n127\n60\n69\nfour forty\n").unwrap_err();
+    assert!(error.to_string().starts_with("line 6:"));
+}
+
+pub fn run(context: AudioContext) -> Result<(), Box<dyn std::error::Error>> {
+    println!("17 :: rendering barka from a Scala tuning");
+    Scale::from_tuning(&Meantone::quarter_comma(A4 * 0.5)).save("./output/meantone.scl")?;
+    KeyboardMapping::linear(57, 69, A4 as f64).save("./output/meantone.kbm")?;
+    let scale = Scale::load("./output/meantone.scl")?;
+    let mapping = KeyboardMapping::load("./output/meantone.kbm")?;
+    let tuning = mapping.tuning(&scale)?;
+    // a synth reading the mapping retunes A3 - A4 to the same notes
+    let retuned = mapping.frequencies(&scale)?[57..=69]
+        .iter()
+        .flatten()
+        .cloned()
+        .collect::<Vec<_>>();
+    let difference = retuned
+        .iter()
+        .zip(tuning.notes(13))
+        .map(|(key, note)| (key - note).abs())
+        .fold(0.0, f32::max);
+    println!(
+        "A3 - A4 from the keyboard mapping: {:.2?}, at most {:.1e} Hz off",
+        retuned, difference
+    );
+    let spec = context.wav_spec(1); // mono
+    let mut writer = hound::WavWriter::create(OUTPUT_FILE, spec)?;
+    for sample in make_barka::<i16>(context, &tuning, &Waveform::Sine)? {
+        writer.write_sample(sample)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,267 @@
+use crate::context::AudioContext;
+use crate::envelope::Envelope;
+use crate::oscillators::Waveform;
+use crate::sample::Sample;
+use crate::tuning::{Edo, JustIntonation, Tuning, A4};
+
+const OUTPUT_FILE: &str = "./output/scales.wav";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mode {
+    Ionian,
+    Dorian,
+    Phrygian,
+    Lydian,
+    Mixolydian,
+    Aeolian,
+    Locrian,
+    HarmonicMinor,
+    MelodicMinor,
+    MajorPentatonic,
+    MinorPentatonic,
+    // steps of the tuning itself, they have to add up to one period
+    Custom(Vec<usize>),
+}
+
+impl Mode {
+    pub const MAJOR: Mode = Mode::Ionian;
+    pub const MINOR: Mode = Mode::Aeolian;
+
+    pub fn from_name(name: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(
+            match name.to_lowercase().replace(&['-', '_'][..], " ").as_str() {
+                "ionian" | "major" => Mode::Ionian,
+                "dorian" => Mode::Dorian,
+                "phrygian" => Mode::Phrygian,
+                "lydian" => Mode::Lydian,
+                "mixolydian" => Mode::Mixolydian,
+                "aeolian" | "minor" | "natural minor" => Mode::Aeolian,
+                "locrian" => Mode::Locrian,
+                "harmonic minor" => Mode::HarmonicMinor,
+                "melodic minor" => Mode::MelodicMinor,
+                "pentatonic" | "major pentatonic" => Mode::MajorPentatonic,
+                "minor pentatonic" => Mode::MinorPentatonic,
+                _ => return Err(format!("unknown mode `{}`", name).into()),
+            },
+        )
+    }
+
+    // semitones between neighbouring degrees, `None` for custom steps
+    fn semitones(&self) -> Option<&'static [usize]> {
+        Some(match self {
+            Mode::Ionian => &[2, 2, 1, 2, 2, 2, 1],
+            Mode::Dorian => &[2, 1, 2, 2, 2, 1, 2],
+            Mode::Phrygian => &[1, 2, 2, 2, 1, 2, 2],
+            Mode::Lydian => &[2, 2, 2, 1, 2, 2, 1],
+            Mode::Mixolydian => &[2, 2, 1, 2, 2, 1, 2],
+            Mode::Aeolian => &[2, 1, 2, 2, 1, 2, 2],
+            Mode::Locrian => &[1, 2, 2, 1, 2, 2, 2],
+            Mode::HarmonicMinor => &[2, 1, 2, 2, 1, 3, 1],
+            Mode::MelodicMinor => &[2, 1, 2, 2, 2, 2, 1],
+            Mode::MajorPentatonic => &[2, 2, 3, 2, 3],
+            Mode::MinorPentatonic => &[3, 2, 2, 3, 2],
+            Mode::Custom(_) => return None,
+        })
+    }
+}
+
+// a mode on a tonic of some tuning, turns scale degrees into note indices of the tuning
+//
+// degree 0 is the tonic, degrees past the last one wrap into the next period
+// and negative degrees go below the tonic
+#[derive(Debug, Clone, PartialEq)]
+pub struct Key {
+    pub tonic: i32,
+    offsets: Vec<i32>,
+    period: i32,
+}
+
+impl Key {
+    // modes are written in semitones, tunings without 12 steps get the step
+    // closest in cents to every semitone
+    pub fn new(
+        tuning: &impl Tuning,
+        tonic: i32,
+        mode: &Mode,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let period = tuning.steps() as i32;
+        let steps = match (mode, mode.semitones()) {
+            (Mode::Custom(steps), _) => steps.iter().map(|step| *step as i32).collect(),
+            (_, Some(semitones)) if period == 12 => {
+                semitones.iter().map(|step| *step as i32).collect()
+            }
+            (_, Some(semitones)) => {
+                let mut semitone = 0;
+                let mut previous = 0;
+                let mut steps = vec![];
+                for step in semitones {
+                    semitone += step;
+                    let cents = (100 * semitone) as f64 * tuning.period().log2();
+                    let index = (0..=period)
+                        .min_by_key(|index| {
+                            let interval = tuning.cents(tonic + index) - tuning.cents(tonic);
+                            ((interval - cents).abs() * 1000.0) as i64
+                        })
+                        .unwrap_or(0);
+                    steps.push(index - previous);
+                    previous = index;
+                }
+                steps
+            }
+            (_, None) => unreachable!("every mode but custom has semitones"),
+        };
+        if steps.iter().any(|step| *step <= 0) {
+            return Err(format!(
+                "{:?} has degrees that fall on the same step of {}",
+                mode,
+                tuning.name()
+            )
+            .into());
+        }
+        if steps.iter().sum::<i32>() != period {
+            return Err(format!(
+                "steps {:?} add up to {} and not to the {} steps of {}",
+                steps,
+                steps.iter().sum::<i32>(),
+                period,
+                tuning.name()
+            )
+            .into());
+        }
+        let offsets = std::iter::once(0)
+            .chain(steps.iter().scan(0, |offset, step| {
+                *offset += step;
+                Some(*offset)
+            }))
+            .take(steps.len())
+            .collect();
+        Ok(Self {
+            tonic,
+            offsets,
+            period,
+        })
+    }
+
+    // degrees per period
+    pub fn size(&self) -> usize {
+        self.offsets.len()
+    }
+
+    // note index of the tuning `degree` is on
+    pub fn degree(&self, degree: i32) -> i32 {
+        let degrees = self.offsets.len() as i32;
+        let (period, degree) = (degree.div_euclid(degrees), degree.rem_euclid(degrees));
+        self.tonic + period * self.period + self.offsets[degree as usize]
+    }
+
+    pub fn degrees(&self, degrees: &[i32]) -> Vec<i32> {
+        degrees.iter().map(|degree| self.degree(*degree)).collect()
+    }
+
+    pub fn frequency(&self, tuning: &impl Tuning, degree: i32) -> f32 {
+        tuning.frequency(self.degree(degree))
+    }
+
+    // triad on `degree`, every other degree of the scale
+    pub fn triad(&self, degree: i32) -> Vec<i32> {
+        self.degrees(&[degree, degree + 2, degree + 4])
+    }
+}
+
+#[test]
+fn test_key() {
+    let twelve = Edo::twelve(220.0);
+    let major = Key::new(&twelve, 0, &Mode::MAJOR).unwrap();
+    assert_eq!(
+        major.degrees(&[0, 1, 2, 6, 7, 9, 14]),
+        vec![0, 2, 4, 11, 12, 16, 24]
+    );
+    assert_eq!(major.degrees(&[-1, -7, -8]), vec![-1, -12, -13]);
+    let harmonic = Key::new(&twelve, 9, &Mode::from_name("harmonic-minor").unwrap()).unwrap();
+    assert_eq!(harmonic.degrees(&[5, 6, 7]), vec![17, 20, 21]);
+    let pentatonic = Key::new(&twelve, 2, &Mode::MinorPentatonic).unwrap();
+    assert_eq!((pentatonic.size(), pentatonic.degree(5)), (5, 14));
+    assert_eq!(
+        Key::new(&twelve, 0, &Mode::MAJOR).unwrap().triad(4),
+        vec![7, 11, 14]
+    );
+
+    // 19 and 31 steps keep the shape of the mode
+    let nineteen = Key::new(&Edo::new(19, 220.0), 0, &Mode::MAJOR).unwrap();
+    assert_eq!(nineteen.degrees(&[1, 2, 3, 4, 7]), vec![3, 6, 8, 11, 19]);
+    let thirty_one = Key::new(&Edo::new(31, 220.0), 0, &Mode::Dorian).unwrap();
+    assert_eq!(thirty_one.degree(7), 31);
+    let custom = Key::new(&Edo::new(7, 220.0), 0, &Mode::Custom(vec![1, 1, 2, 1, 2])).unwrap();
+    assert_eq!(custom.degrees(&[3, 5]), vec![4, 7]);
+
+    assert!(Key::new(&twelve, 0, &Mode::Custom(vec![2, 2])).is_err());
+    assert!(Key::new(&Edo::new(5, 220.0), 0, &Mode::MAJOR).is_err());
+    assert!(Mode::from_name("hypodorian").is_err());
+}
+
+// one note per degree, up and down the scale
+fn play_degrees(
+    context: AudioContext,
+    tuning: &impl Tuning,
+    key: &Key,
+    degrees: &[i32],
+) -> Vec<f32> {
+    let envelope = Envelope::adsr(0.01, 0.1, 0.7, 0.05);
+    let note_length = context.samples(0.25);
+    let release = context.samples(envelope.release_time());
+    degrees
+        .iter()
+        .flat_map(|degree| {
+            let voice = Waveform::Triangle.wave(context, key.frequency(tuning, *degree));
+            envelope
+                .note(context, voice, note_length - release)
+                .chain(std::iter::repeat(0.0))
+                .take(note_length)
+        })
+        .collect()
+}
+
+pub fn run(context: AudioContext) -> Result<(), Box<dyn std::error::Error>> {
+    println!("21 :: playing modes in different tunings");
+    let melody = vec![0, 1, 2, 3, 4, 5, 6, 7, 4, 2, 0, -1, -3, 0];
+    let twelve = Edo::twelve(A4 * 0.5);
+    let just = JustIntonation::new(A4 * 0.5);
+    let nineteen = Edo::new(19, A4 * 0.5);
+    let mut song = play_degrees(
+        context,
+        &twelve,
+        &Key::new(&twelve, 5, &Mode::Dorian)?,
+        &melody,
+    );
+    song.extend(play_degrees(
+        context,
+        &just,
+        &Key::new(&just, 0, &Mode::HarmonicMinor)?,
+        &melody,
+    ));
+    song.extend(play_degrees(
+        context,
+        &nineteen,
+        &Key::new(&nineteen, 0, &Mode::MAJOR)?,
+        &melody,
+    ));
+    song.extend(play_degrees(
+        context,
+        &nineteen,
+        &Key::new(&nineteen, 0, &Mode::MINOR)?,
+        &melody,
+    ));
+    // a five note scale of 7 equal steps, once up and down
+    let seven = Edo::new(7, A4 * 0.5);
+    let custom = Key::new(&seven, 0, &Mode::Custom(vec![1, 1, 2, 1, 2]))?;
+    let size = custom.size() as i32;
+    let up_and_down = (0..size).chain((0..=size).rev()).collect::<Vec<_>>();
+    song.extend(play_degrees(context, &seven, &custom, &up_and_down));
+
+    let spec = context.wav_spec(1); // mono
+    let mut writer = hound::WavWriter::create(OUTPUT_FILE, spec)?;
+    for sample in song {
+        writer.write_sample(i16::from_synth(sample))?;
+    }
+    Ok(())
+}
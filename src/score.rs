@@ -0,0 +1,485 @@
+use std::path::Path;
+
+use crate::context::AudioContext;
+use crate::envelope::Envelope;
+use crate::oscillators::{Instrument, Waveform};
+use crate::pitch::{ratio_of_cents, ConcertPitch, Keyboard, Note};
+use crate::sample::Sample;
+use crate::scale::{Key, Mode};
+use crate::tuning::{Edo, Pythagorean, Tuning};
+
+const INPUT_FILE: &str = "./data/barka.score";
+const OUTPUT_FILE: &str = "./output/score.wav";
+
+// A small text format for melodies, one token after another:
+//
+//   # comment lines start with `#`
+//   title Barka
+//   tempo 100              quarter notes per minute, from here on
+//   key A3 major           tonic and mode for scale degrees
+//   mf                     dynamics, ppp - fff
+//   C#5/4. B4/8 A4/2       note names with a duration, 4 is a quarter, dots lengthen
+//   0/4 2 4 -1             scale degrees of the key, the duration carries over
+//   r/8                    rest
+//   A4/2~ A4/8             a tie joins two notes of the same pitch
+//   |: C4 D4 :| E4 :|x3    repeats, twice or as many times as asked
+//   |                      bar lines are only there to read
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScorePitch {
+    Name(Note),
+    Degree { degree: i32, tonic: i32, mode: Mode },
+}
+
+// one note, in seconds from the start of the score
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScoreNote {
+    pub start: f32,
+    pub length: f32,
+    pub pitch: ScorePitch,
+    pub velocity: f32,
+    // where it was written, for errors while rendering
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Score {
+    pub title: String,
+    pub notes: Vec<ScoreNote>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Note {
+        pitch: NotePitch,
+        beats: Option<f32>,
+        tie: bool,
+    },
+    Rest(Option<f32>),
+    Dynamic(f32),
+    Tempo(f32),
+    Key(i32, Mode),
+    RepeatStart,
+    RepeatEnd(usize),
+}
+
+// tokens with the line and column they were written at
+type Tokens = Vec<(usize, usize, Token)>;
+
+#[derive(Debug, Clone, PartialEq)]
+enum NotePitch {
+    Name(Note),
+    Degree(i32),
+}
+
+fn error(line: usize, column: usize, message: &str) -> Box<dyn std::error::Error> {
+    format!("line {}, column {}: {}", line, column, message).into()
+}
+
+// whitespace separated words and the column they start at, counted from 1
+fn words(line: &str) -> Vec<(usize, &str)> {
+    let mut words = vec![];
+    let mut start = None;
+    for (column, (index, c)) in line.char_indices().enumerate() {
+        match (c.is_whitespace(), start) {
+            (false, None) => start = Some((column + 1, index)),
+            (true, Some((word_column, word_start))) => {
+                words.push((word_column, &line[word_start..index]));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some((column, index)) = start {
+        words.push((column, &line[index..]));
+    }
+    words
+}
+
+fn dynamic(word: &str) -> Option<f32> {
+    Some(match word {
+        "ppp" => 0.15,
+        "pp" => 0.25,
+        "p" => 0.4,
+        "mp" => 0.55,
+        "mf" => 0.7,
+        "f" => 0.85,
+        "ff" => 0.95,
+        "fff" => 1.0,
+        _ => return None,
+    })
+}
+
+// `4` is a quarter note, one beat, `8.` a dotted eighth
+fn parse_duration(text: &str) -> Option<f32> {
+    let dots = text.len() - text.trim_end_matches('.').len();
+    let beats = match text.trim_end_matches('.') {
+        "1" => 4.0,
+        "2" => 2.0,
+        "4" => 1.0,
+        "8" => 0.5,
+        "16" => 0.25,
+        "32" => 0.125,
+        "64" => 0.0625,
+        _ => return None,
+    };
+    Some((0..=dots).map(|dot| beats / 2.0f32.powi(dot as i32)).sum())
+}
+
+fn parse_word(word: &str, line: usize, column: usize) -> Result<Token, Box<dyn std::error::Error>> {
+    if let Some(velocity) = dynamic(word) {
+        return Ok(Token::Dynamic(velocity));
+    }
+    match word {
+        "|:" => return Ok(Token::RepeatStart),
+        ":|" => return Ok(Token::RepeatEnd(2)),
+        _ => {}
+    }
+    if let Some(times) = word.strip_prefix(":|x") {
+        return match times.parse::<usize>() {
+            Ok(times) if times > 0 => Ok(Token::RepeatEnd(times)),
+            _ => Err(error(
+                line,
+                column,
+                &format!("`{}` is not a repeat count", times),
+            )),
+        };
+    }
+    let tie = word.ends_with('~');
+    let word = word.trim_end_matches('~');
+    let (pitch, beats) = match word.find('/') {
+        Some(split) => {
+            let duration = &word[split + 1..];
+            let beats = parse_duration(duration).ok_or_else(|| {
+                error(
+                    line,
+                    column + split + 1,
+                    &format!("`{}` is not a duration, expected 1, 2, 4, 8, 16, 32 or 64 with optional dots", duration),
+                )
+            })?;
+            (&word[..split], Some(beats))
+        }
+        None => (word, None),
+    };
+    if pitch == "r" {
+        if tie {
+            return Err(error(line, column, "rests can't be tied"));
+        }
+        return Ok(Token::Rest(beats));
+    }
+    let pitch = match pitch.parse::<i32>() {
+        Ok(degree) => NotePitch::Degree(degree),
+        Err(_) => NotePitch::Name(
+            Note::parse(pitch).map_err(|message| error(line, column, &message.to_string()))?,
+        ),
+    };
+    Ok(Token::Note { pitch, beats, tie })
+}
+
+// every token of the file with its position, directive lines become tokens too
+fn tokenize(text: &str) -> Result<(String, Tokens), Box<dyn std::error::Error>> {
+    let mut title = String::new();
+    let mut tokens = vec![];
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        if line.trim_start().starts_with('#') {
+            continue;
+        }
+        let words = words(line);
+        match words.first() {
+            None => continue,
+            Some((_, "title")) => {
+                title = line.trim_start()["title".len()..].trim().to_string();
+                continue;
+            }
+            Some((column, "tempo")) => {
+                let tempo = match words.get(1).map(|(_, word)| word.parse::<f32>()) {
+                    Some(Ok(tempo)) if tempo > 0.0 && words.len() == 2 => tempo,
+                    _ => {
+                        return Err(error(
+                            line_number,
+                            *column,
+                            "expected `tempo <beats per minute>`",
+                        ))
+                    }
+                };
+                tokens.push((line_number, *column, Token::Tempo(tempo)));
+                continue;
+            }
+            Some((column, "key")) => {
+                let (tonic, mode) = match &words[1..] {
+                    [(tonic_column, tonic), (mode_column, _), ..] => (
+                        Note::parse(tonic).map_err(|message| {
+                            error(line_number, *tonic_column, &message.to_string())
+                        })?,
+                        Mode::from_name(
+                            &words[2..]
+                                .iter()
+                                .map(|(_, word)| *word)
+                                .collect::<Vec<_>>()
+                                .join(" "),
+                        )
+                        .map_err(|message| {
+                            error(line_number, *mode_column, &message.to_string())
+                        })?,
+                    ),
+                    _ => return Err(error(line_number, *column, "expected `key <tonic> <mode>`")),
+                };
+                tokens.push((line_number, *column, Token::Key(tonic.key, mode)));
+                continue;
+            }
+            _ => {}
+        }
+        for (column, word) in words {
+            if word == "|" {
+                continue;
+            }
+            tokens.push((line_number, column, parse_word(word, line_number, column)?));
+        }
+    }
+    Ok((title, tokens))
+}
+
+// plays every repeated section again, innermost first
+fn expand_repeats(tokens: Tokens) -> Result<Tokens, Box<dyn std::error::Error>> {
+    let mut expanded = vec![];
+    let mut starts = vec![];
+    for (line, column, token) in tokens {
+        match token {
+            Token::RepeatStart => starts.push(expanded.len()),
+            // a repeat without a start goes back to the beginning
+            Token::RepeatEnd(times) => {
+                let start = starts.pop().unwrap_or(0);
+                let section = expanded[start..].to_vec();
+                for _ in 1..times {
+                    expanded.extend(section.iter().cloned());
+                }
+            }
+            token => expanded.push((line, column, token)),
+        }
+    }
+    if !starts.is_empty() {
+        return Err("a repeat `|:` is never closed with `:|`".into());
+    }
+    Ok(expanded)
+}
+
+impl Score {
+    pub fn from_text(text: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let (title, tokens) = tokenize(text)?;
+        let mut notes: Vec<ScoreNote> = vec![];
+        let mut time = 0.0;
+        let mut beat_length = 60.0 / 120.0;
+        let mut beats = 1.0;
+        let mut velocity = dynamic("mf").unwrap_or(1.0);
+        let mut key = None;
+        let mut tied = false;
+        for (line, column, token) in expand_repeats(tokens)? {
+            match token {
+                Token::Tempo(tempo) => beat_length = 60.0 / tempo,
+                Token::Dynamic(dynamic) => velocity = dynamic,
+                Token::Key(tonic, mode) => key = Some((tonic, mode)),
+                Token::Rest(rest_beats) => {
+                    if tied {
+                        return Err(error(line, column, "a tie has to go to a note, not a rest"));
+                    }
+                    beats = rest_beats.unwrap_or(beats);
+                    time += beats * beat_length;
+                }
+                Token::Note {
+                    pitch,
+                    beats: note_beats,
+                    tie,
+                } => {
+                    beats = note_beats.unwrap_or(beats);
+                    let pitch = match (pitch, &key) {
+                        (NotePitch::Name(note), _) => ScorePitch::Name(note),
+                        (NotePitch::Degree(degree), Some((tonic, mode))) => ScorePitch::Degree {
+                            degree,
+                            tonic: *tonic,
+                            mode: mode.clone(),
+                        },
+                        (NotePitch::Degree(_), None) => {
+                            return Err(error(
+                                line,
+                                column,
+                                "scale degrees need a `key` line before them",
+                            ))
+                        }
+                    };
+                    let length = beats * beat_length;
+                    match notes.last_mut() {
+                        Some(previous) if tied => {
+                            if previous.pitch != pitch {
+                                return Err(error(
+                                    line,
+                                    column,
+                                    "a tie has to go to the same pitch",
+                                ));
+                            }
+                            previous.length += length;
+                        }
+                        _ => notes.push(ScoreNote {
+                            start: time,
+                            length,
+                            pitch,
+                            velocity,
+                            line,
+                        }),
+                    }
+                    tied = tie;
+                    time += length;
+                }
+                Token::RepeatStart | Token::RepeatEnd(_) => unreachable!("repeats are expanded"),
+            }
+        }
+        if tied {
+            return Err("the last note is tied to nothing".into());
+        }
+        Ok(Self { title, notes })
+    }
+
+    pub fn load<T: AsRef<Path>>(path: T) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::from_text(&std::fs::read_to_string(path)?)
+    }
+
+    // seconds until the last note ends
+    pub fn length(&self) -> f32 {
+        self.notes
+            .iter()
+            .map(|note| note.start + note.length)
+            .fold(0.0, f32::max)
+    }
+
+    // note index of `tuning` and cents to add, note names are MIDI keys so the tuning
+    // should be a `Keyboard` or have MIDI keys as indices in some other way
+    pub fn index(
+        tuning: &impl Tuning,
+        note: &ScoreNote,
+    ) -> Result<(i32, f64), Box<dyn std::error::Error>> {
+        Ok(match &note.pitch {
+            ScorePitch::Name(name) => (name.key, name.cents),
+            ScorePitch::Degree {
+                degree,
+                tonic,
+                mode,
+            } => {
+                let key = Key::new(tuning, *tonic, mode)
+                    .map_err(|message| format!("line {}: {}", note.line, message))?;
+                (key.degree(*degree), 0.0)
+            }
+        })
+    }
+
+    // every note through `instrument`, shaped by `envelope`, the release rings past the note
+    pub fn render(
+        &self,
+        context: AudioContext,
+        tuning: &impl Tuning,
+        instrument: &impl Instrument,
+        envelope: &Envelope,
+    ) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
+        let release = context.samples(envelope.release_time());
+        let mut song = vec![0.0; context.samples(self.length()) + release];
+        for note in self.notes.iter() {
+            let (index, cents) = Self::index(tuning, note)?;
+            let frequency = (tuning.frequency(index) as f64 * ratio_of_cents(cents)) as f32;
+            let start = context.samples(note.start);
+            // the gate closes before the next note, like `make_barka`
+            let gate = context.samples(note.length).saturating_sub(release);
+            let voice = instrument.voice(context, frequency);
+            for (sample, value) in song[start..]
+                .iter_mut()
+                .zip(envelope.note(context, voice, gate))
+            {
+                *sample += value * note.velocity;
+            }
+        }
+        Ok(song)
+    }
+}
+
+#[test]
+fn test_score() {
+    let score = Score::from_text(
+        "title test\ntempo 60\nkey D4 dorian\n0/4 2/8. -1 r/2 |: 1/8~ 1/16 :|\nf A4+10/1\n",
+    )
+    .unwrap();
+    assert_eq!(score.title, "test");
+    let starts = score
+        .notes
+        .iter()
+        .map(|note| note.start)
+        .collect::<Vec<_>>();
+    assert_eq!(starts, vec![0.0, 1.0, 1.75, 4.5, 5.25, 6.0]);
+    assert_eq!(score.notes[4].length, 0.75); // tied
+    assert_eq!(score.length(), 10.0);
+    assert_eq!(
+        score.notes[5].pitch,
+        ScorePitch::Name(Note::new(69).with_cents(10.0))
+    );
+    assert_eq!(score.notes[5].velocity, 0.85);
+    let twelve = Keyboard::new(Edo::twelve(1.0), Note::new(60), ConcertPitch::default());
+    let indices = score
+        .notes
+        .iter()
+        .map(|note| Score::index(&twelve, note).unwrap().0)
+        .collect::<Vec<_>>();
+    assert_eq!(indices, vec![62, 65, 60, 64, 64, 69]);
+
+    let error = |text: &str| Score::from_text(text).unwrap_err().to_string();
+    assert_eq!(
+        error("C4/4 D4/3"),
+        "line 1, column 9: `3` is not a duration, expected 1, 2, 4, 8, 16, 32 or 64 with optional dots"
+    );
+    assert!(error("C4\n  C4 H4").starts_with("line 2, column 6: `H4` is not a note name"));
+    assert!(error("C4 2").starts_with("line 1, column 4: scale degrees need a `key`"));
+    assert!(error("C4~ D4").starts_with("line 1, column 5: a tie has to go to the same pitch"));
+    assert!(error("key C4 hypodorian").starts_with("line 1, column 8: unknown mode"));
+    assert!(error("tempo fast").starts_with("line 1, column 1:"));
+    assert!(error("|: C4").contains("never closed"));
+}
+
+#[test]
+fn test_barka_score() {
+    // the score and the melody in `barka_melody` are the same tune, A3 is note 0 of the tuning
+    let score = Score::from_text(include_str!("../data/barka.score")).unwrap();
+    let twelve = Edo::twelve(220.0);
+    let eighth = 60.0 / 100.0 / 2.0;
+    let notes = score
+        .notes
+        .iter()
+        .map(|note| {
+            let key = Score::index(&twelve, note).unwrap().0 - 57;
+            (key, (note.length / eighth).round() as usize)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        notes,
+        crate::pythagorean_chords::barka_melody(&twelve).unwrap()
+    );
+}
+
+pub fn run(context: AudioContext) -> Result<(), Box<dyn std::error::Error>> {
+    println!("22 :: rendering a score file");
+    let score = Score::load(INPUT_FILE)?;
+    println!(
+        "{}, {} notes, {:.1} s",
+        score.title,
+        score.notes.len(),
+        score.length()
+    );
+    // pythagorean on the left, equal temperament on the right, like barka
+    let tonic = Note::parse("A3")?;
+    let pythagorean = Keyboard::new(Pythagorean::new(1.0), tonic, ConcertPitch::default());
+    let equal = Keyboard::new(Edo::twelve(1.0), tonic, ConcertPitch::default());
+    let envelope = Envelope::adsr(0.01, 0.1, 0.8, 0.05);
+    let left = score.render(context, &pythagorean, &Waveform::Sine, &envelope)?;
+    let right = score.render(context, &equal, &Waveform::Sine, &envelope)?;
+    let spec = context.wav_spec(2);
+    let mut writer = hound::WavWriter::create(OUTPUT_FILE, spec)?;
+    for (left, right) in left.into_iter().zip(right) {
+        writer.write_sample(i16::from_synth(left))?;
+        writer.write_sample(i16::from_synth(right))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,399 @@
+use crate::context::AudioContext;
+use crate::envelope::Envelope;
+use crate::fm::Patch;
+use crate::oscillators::Instrument;
+use crate::pitch::{ConcertPitch, Keyboard, Note};
+use crate::pluck::PluckedString;
+use crate::sample::Sample;
+use crate::scale::{Key, Mode};
+use crate::tuning::{Pythagorean, Tuning};
+use crate::voices::{Event, Message, Performance, Stealing, VoiceManager};
+
+const OUTPUT_FILE: &str = "./output/sequencer.wav";
+
+// how the tempo gets to a change from the one before it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Ramp {
+    Step,
+    // beats per minute change evenly with every tick
+    Linear,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TempoChange {
+    pub tick: u64,
+    pub bpm: f64, // quarter notes per minute
+    pub ramp: Ramp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MeterChange {
+    pub bar: u64,
+    pub numerator: u64,
+    pub denominator: u64,
+}
+
+// musical time, ticks and bars of it, and where that falls in seconds and samples
+//
+// ticks count `ppq` to a quarter note, bars and beats count from 0, a beat is
+// whatever the denominator of the time signature says
+#[derive(Debug, Clone, PartialEq)]
+pub struct Timeline {
+    pub ppq: u64,
+    tempos: Vec<TempoChange>,
+    meters: Vec<MeterChange>,
+}
+
+impl Timeline {
+    // in 4/4 until a meter change says otherwise
+    pub fn new(ppq: u64, bpm: f64) -> Result<Self, Box<dyn std::error::Error>> {
+        if ppq == 0 {
+            return Err("there have to be some ticks to a quarter note".into());
+        }
+        let timeline = Self {
+            ppq,
+            tempos: vec![],
+            meters: vec![MeterChange {
+                bar: 0,
+                numerator: 4,
+                denominator: 4,
+            }],
+        };
+        timeline.with_tempo(0, bpm, Ramp::Step)
+    }
+
+    // a change on the same tick replaces the one that was there
+    pub fn with_tempo(
+        mut self,
+        tick: u64,
+        bpm: f64,
+        ramp: Ramp,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if !(bpm > 0.0 && bpm.is_finite()) {
+            return Err(format!("a tempo of {} beats per minute", bpm).into());
+        }
+        self.tempos.retain(|change| change.tick != tick);
+        self.tempos.push(TempoChange { tick, bpm, ramp });
+        self.tempos.sort_by_key(|change| change.tick);
+        Ok(self)
+    }
+
+    // from the start of `bar` on
+    pub fn with_meter(
+        mut self,
+        bar: u64,
+        numerator: u64,
+        denominator: u64,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if numerator == 0 || !denominator.is_power_of_two() || denominator > 64 {
+            return Err(format!("{}/{} is not a time signature", numerator, denominator).into());
+        }
+        if !(self.ppq * 4).is_multiple_of(denominator) {
+            return Err(format!(
+                "a 1/{} beat is not a whole number of ticks at {} to a quarter",
+                denominator, self.ppq
+            )
+            .into());
+        }
+        self.meters.retain(|change| change.bar != bar);
+        self.meters.push(MeterChange {
+            bar,
+            numerator,
+            denominator,
+        });
+        self.meters.sort_by_key(|change| change.bar);
+        Ok(self)
+    }
+
+    pub fn tempos(&self) -> &[TempoChange] {
+        &self.tempos
+    }
+
+    pub fn meter(&self, bar: u64) -> MeterChange {
+        *self
+            .meters
+            .iter()
+            .rev()
+            .find(|change| change.bar <= bar)
+            .unwrap_or(&self.meters[0])
+    }
+
+    pub fn beat_ticks(&self, bar: u64) -> u64 {
+        self.ppq * 4 / self.meter(bar).denominator
+    }
+
+    pub fn bar_ticks(&self, bar: u64) -> u64 {
+        self.beat_ticks(bar) * self.meter(bar).numerator
+    }
+
+    // tick `tick` of beat `beat` of bar `bar`
+    pub fn position(&self, bar: u64, beat: u64, tick: u64) -> u64 {
+        let bars = (0..bar).map(|bar| self.bar_ticks(bar)).sum::<u64>();
+        bars + beat * self.beat_ticks(bar) + tick
+    }
+
+    // bar, beat and tick `tick` falls on
+    pub fn bar_beat(&self, tick: u64) -> (u64, u64, u64) {
+        let (mut bar, mut start) = (0, 0);
+        while start + self.bar_ticks(bar) <= tick {
+            start += self.bar_ticks(bar);
+            bar += 1;
+        }
+        let beat_ticks = self.beat_ticks(bar);
+        (
+            bar,
+            (tick - start) / beat_ticks,
+            (tick - start) % beat_ticks,
+        )
+    }
+
+    // tempo the change before `tick` starts from and the one it is heading to
+    fn segment(&self, tick: u64) -> (TempoChange, Option<TempoChange>) {
+        let next = self.tempos.iter().position(|change| change.tick > tick);
+        match next {
+            Some(0) => (self.tempos[0], None),
+            Some(next) => (self.tempos[next - 1], Some(self.tempos[next])),
+            None => (self.tempos[self.tempos.len() - 1], None),
+        }
+    }
+
+    pub fn bpm(&self, tick: u64) -> f64 {
+        match self.segment(tick) {
+            (from, Some(to)) if to.ramp == Ramp::Linear => {
+                let progress = (tick - from.tick) as f64 / (to.tick - from.tick) as f64;
+                from.bpm + (to.bpm - from.bpm) * progress
+            }
+            (from, _) => from.bpm,
+        }
+    }
+
+    // seconds from `from` to `tick`, with both in the same segment
+    fn segment_seconds(&self, from: TempoChange, to: Option<TempoChange>, tick: u64) -> f64 {
+        let beats = tick.saturating_sub(from.tick) as f64 / self.ppq as f64;
+        match to {
+            // 60 / bpm seconds per beat, and bpm grows by `slope` every beat
+            Some(to) if to.ramp == Ramp::Linear && to.bpm != from.bpm => {
+                let slope = (to.bpm - from.bpm) / ((to.tick - from.tick) as f64 / self.ppq as f64);
+                60.0 / slope * ((from.bpm + slope * beats) / from.bpm).ln()
+            }
+            _ => beats * 60.0 / from.bpm,
+        }
+    }
+
+    // exact, nothing adds up from one note to the next
+    pub fn seconds(&self, tick: u64) -> f64 {
+        let mut seconds = 0.0;
+        for pair in self.tempos.windows(2) {
+            if tick <= pair[1].tick {
+                return seconds + self.segment_seconds(pair[0], Some(pair[1]), tick);
+            }
+            seconds += self.segment_seconds(pair[0], Some(pair[1]), pair[1].tick);
+        }
+        let (last, _) = self.segment(tick);
+        seconds + self.segment_seconds(last, None, tick)
+    }
+
+    // the sample `tick` starts on, rounded to the closest one
+    pub fn sample(&self, context: AudioContext, tick: u64) -> usize {
+        (self.seconds(tick) * context.sample_rate as f64).round() as usize
+    }
+}
+
+// one note on the timeline, `key` is a note index of the tuning it gets played in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SequencedNote {
+    pub tick: u64,
+    pub length: u64,
+    pub key: i32,
+    pub velocity: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sequencer {
+    pub timeline: Timeline,
+    pub notes: Vec<SequencedNote>,
+}
+
+impl Sequencer {
+    pub fn new(timeline: Timeline) -> Self {
+        Self {
+            timeline,
+            notes: vec![],
+        }
+    }
+
+    pub fn note(&mut self, tick: u64, length: u64, key: i32, velocity: f32) {
+        self.notes.push(SequencedNote {
+            tick,
+            length,
+            key,
+            velocity,
+        });
+    }
+
+    // note on and note off events on the samples the timeline puts them,
+    // a note off comes before a note on of the same sample
+    pub fn events(&self, context: AudioContext, tuning: &impl Tuning) -> Vec<Event> {
+        let mut events = vec![];
+        for note in self.notes.iter() {
+            let start = self.timeline.sample(context, note.tick);
+            let end = self.timeline.sample(context, note.tick + note.length);
+            let freq = tuning.frequency(note.key);
+            events.push(Event::note_on(start, note.key, freq, note.velocity));
+            events.push(Event::note_off(end, note.key));
+        }
+        events.sort_by_key(|event| {
+            (
+                event.time,
+                !matches!(event.message, Message::NoteOff { .. }),
+            )
+        });
+        events
+    }
+
+    // every note through any instrument, with a voice allocator
+    pub fn play<I: Instrument>(
+        &self,
+        context: AudioContext,
+        tuning: &impl Tuning,
+        instrument: I,
+        envelope: Envelope,
+        max_voices: usize,
+    ) -> Performance<I> {
+        let manager = VoiceManager::new(
+            context,
+            instrument,
+            envelope,
+            max_voices,
+            Stealing::SameNote,
+        );
+        manager.render(self.events(context, tuning))
+    }
+}
+
+#[test]
+fn test_timeline() {
+    let context = AudioContext::default();
+    let steady = Timeline::new(960, 120.0).unwrap();
+    assert_eq!(steady.seconds(960), 0.5);
+    assert_eq!(steady.sample(context, 3 * 960), 66150);
+
+    // 60 to 120 bpm over 4 beats, bpm grow 15 a beat: 60 / 15 * ln(2) seconds
+    let ramp = Timeline::new(960, 60.0)
+        .unwrap()
+        .with_tempo(4 * 960, 120.0, Ramp::Linear)
+        .unwrap();
+    assert!((ramp.seconds(4 * 960) - 4.0 * 2.0f64.ln()).abs() < 1e-12);
+    assert_eq!(ramp.bpm(2 * 960), 90.0);
+    assert!((ramp.seconds(5 * 960) - ramp.seconds(4 * 960) - 0.5).abs() < 1e-12);
+    assert!(ramp.seconds(2 * 960) > 2.0 * 60.0 / 90.0);
+
+    let meters = Timeline::new(960, 120.0)
+        .unwrap()
+        .with_meter(0, 6, 8)
+        .unwrap()
+        .with_meter(2, 4, 4)
+        .unwrap();
+    assert_eq!(meters.bar_ticks(1), 2880);
+    assert_eq!(meters.position(2, 1, 10), 6730);
+    assert_eq!(meters.bar_beat(6730), (2, 1, 10));
+    assert_eq!(meters.bar_beat(2879), (0, 5, 479));
+    assert!(meters.clone().with_meter(3, 3, 6).is_err());
+    assert!(Timeline::new(960, 0.0).is_err());
+
+    // a repeated note lets go before it starts again
+    let mut sequencer = Sequencer::new(steady);
+    sequencer.note(0, 960, 0, 1.0);
+    sequencer.note(960, 960, 0, 1.0);
+    let events = sequencer.events(context, &crate::tuning::Edo::twelve(220.0));
+    assert_eq!(events[1], Event::note_off(22050, 0));
+    assert_eq!(events[2].time, 22050);
+}
+
+pub fn run(context: AudioContext) -> Result<(), Box<dyn std::error::Error>> {
+    println!("24 :: sequencing through tempo ramps and changing meters");
+    // two bars of 4/4, speeding up into two bars of 7/8, then slowing down to the end
+    let ppq = 960;
+    let meters = Timeline::new(ppq, 84.0)?
+        .with_meter(4, 7, 8)?
+        .with_meter(6, 4, 4)?;
+    let bars = [2, 4, 6, 9]
+        .iter()
+        .map(|bar| meters.position(*bar, 0, 0))
+        .collect::<Vec<_>>();
+    let timeline = meters
+        .with_tempo(bars[0], 84.0, Ramp::Step)?
+        .with_tempo(bars[1], 132.0, Ramp::Linear)?
+        .with_tempo(bars[2], 132.0, Ramp::Step)?
+        .with_tempo(bars[3], 66.0, Ramp::Linear)?;
+    for change in timeline.tempos() {
+        let (bar, beat, _) = timeline.bar_beat(change.tick);
+        println!(
+            "bar {} beat {} at tick {}, {:.1} bpm {:?}, sample {}",
+            bar + 1,
+            beat + 1,
+            change.tick,
+            change.bpm,
+            change.ramp,
+            timeline.sample(context, change.tick)
+        );
+    }
+
+    let tuning = Keyboard::new(
+        Pythagorean::new(1.0),
+        Note::parse("D3")?,
+        ConcertPitch::default(),
+    );
+    let key = Key::new(&tuning, Note::parse("D3")?.key, &Mode::Dorian)?;
+    let mut chords = Sequencer::new(timeline.clone());
+    let mut strings = Sequencer::new(timeline.clone());
+    let roots = [0, 3, 4, 0, 3, 6, 4, 4, 0];
+    for (bar, root) in roots.iter().enumerate() {
+        let bar = bar as u64;
+        let start = timeline.position(bar, 0, 0);
+        for note in key.triad(*root - 7) {
+            chords.note(start, timeline.bar_ticks(bar), note, 0.3);
+        }
+        // an arpeggio on every beat of the bar, up and back down
+        let beats = timeline.meter(bar).numerator as i32;
+        for beat in 0..beats {
+            let step = if beat < beats / 2 + 1 {
+                beat
+            } else {
+                beats - beat
+            };
+            let tick = timeline.position(bar, beat as u64, 0);
+            let length = timeline.beat_ticks(bar);
+            strings.note(tick, length, key.degree(*root + 2 * step), 0.6);
+        }
+    }
+    let last = timeline.position(roots.len() as u64, 0, 0);
+    strings.note(last, 2 * ppq, key.degree(7), 0.6);
+    chords.note(last, 2 * ppq, key.degree(-7), 0.3);
+
+    let piano = chords
+        .play(
+            context,
+            &tuning,
+            Patch::electric_piano(),
+            Envelope::adsr(0.005, 0.3, 0.6, 0.4),
+            8,
+        )
+        .collect::<Vec<_>>();
+    let plucked = strings
+        .play(
+            context,
+            &tuning,
+            PluckedString::default(),
+            Envelope::adsr(0.001, 0.1, 0.9, 0.1),
+            4,
+        )
+        .collect::<Vec<_>>();
+    let spec = context.wav_spec(1); // mono
+    let mut writer = hound::WavWriter::create(OUTPUT_FILE, spec)?;
+    for index in 0..piano.len().max(plucked.len()) {
+        let sample = piano.get(index).unwrap_or(&0.0) + plucked.get(index).unwrap_or(&0.0);
+        writer.write_sample(i16::from_synth(sample * 0.5))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,326 @@
+use crate::context::AudioContext;
+use crate::envelope::Envelope;
+use crate::oscillators::Waveform;
+use crate::pitch::{cents, ConcertPitch, Note};
+use crate::pythagorean_chords::{chord, make_barka};
+use crate::sample::Sample;
+use crate::tuning::Tuning;
+
+const OUTPUT_FILE: &str = "./output/temperaments.wav";
+
+pub const PYTHAGOREAN_COMMA: f64 = 531441.0 / 524288.0;
+pub const SYNTONIC_COMMA: f64 = 81.0 / 80.0;
+pub const SCHISMA: f64 = 32805.0 / 32768.0;
+
+const PURE_FIFTH: f64 = 3.0 / 2.0;
+const NAMES: [&str; 12] = [
+    "C", "C#", "D", "Eb", "E", "F", "F#", "G", "G#", "A", "Bb", "B",
+];
+
+// 12 notes tuned along a circle of fifths, the last fifth closes the circle
+// and takes whatever the others left over, a wolf if they don't add up
+#[derive(Debug, Clone, PartialEq)]
+pub struct Temperament {
+    name: String,
+    ratios: [f64; 12],
+    pub base: f32,
+}
+
+impl Temperament {
+    // walks 11 fifths up from step `start`, octave reducing on the way
+    fn chain(name: &str, start: usize, fifths: [f64; 11], base: f32) -> Self {
+        let mut ratios = [1.0; 12];
+        let mut ratio = 1.0;
+        for (index, fifth) in fifths.iter().enumerate() {
+            ratio *= fifth;
+            ratio /= 2.0f64.powf(ratio.log2().floor());
+            ratios[(start + 7 * (index + 1)) % 12] = ratio;
+        }
+        // the chain starts anywhere, step 0 is still 1/1
+        let tonic = ratios[0];
+        for ratio in ratios.iter_mut() {
+            *ratio /= tonic;
+            *ratio /= 2.0f64.powf(ratio.log2().floor());
+        }
+        Self {
+            name: name.to_string(),
+            ratios,
+            base,
+        }
+    }
+
+    // any 11 fifths from step `start` on, e.g. `start` 3 for a chain from Eb to G#
+    pub fn from_fifths(
+        name: &str,
+        start: usize,
+        fifths: [f64; 11],
+        base: f32,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if start >= 12 {
+            return Err(format!("the chain has to start on a step 0 - 11, not {}", start).into());
+        }
+        let temperament = Self::chain(name, start, fifths, base);
+        if let Some(step) =
+            (1..12).find(|step| temperament.ratios[*step] <= temperament.ratios[step - 1])
+        {
+            return Err(format!(
+                "fifths of {:.2} - {:.2} cents put {} below {}",
+                fifths.iter().map(|f| cents(*f)).fold(f64::MAX, f64::min),
+                fifths.iter().map(|f| cents(*f)).fold(f64::MIN, f64::max),
+                NAMES[step],
+                NAMES[step - 1]
+            )
+            .into());
+        }
+        Ok(temperament)
+    }
+
+    // every fifth narrower than pure by `tempering` cents
+    pub fn from_tempering(
+        name: &str,
+        start: usize,
+        tempering: [f64; 11],
+        base: f32,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut fifths = [PURE_FIFTH; 11];
+        for (fifth, cents) in fifths.iter_mut().zip(tempering.iter()) {
+            *fifth /= 2.0f64.powf(cents / 1200.0);
+        }
+        Self::from_fifths(name, start, fifths, base)
+    }
+
+    // every fifth narrower than pure by its share of `comma`, e.g. 1/4 syntonic comma
+    pub fn from_comma_split(
+        name: &str,
+        start: usize,
+        comma: f64,
+        split: [f64; 11],
+        base: f32,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut tempering = [0.0; 11];
+        for (cents_narrower, share) in tempering.iter_mut().zip(split.iter()) {
+            *cents_narrower = share * cents(comma);
+        }
+        Self::from_tempering(name, start, tempering, base)
+    }
+
+    // C-G, G-D, D-A and B-F# a quarter Pythagorean comma narrow
+    pub fn werckmeister_iii(base: f32) -> Self {
+        let q = 0.25;
+        Self::from_comma_split(
+            "Werckmeister III",
+            0,
+            PYTHAGOREAN_COMMA,
+            [q, q, q, 0.0, 0.0, q, 0.0, 0.0, 0.0, 0.0, 0.0],
+            base,
+        )
+        .expect("Werckmeister III is a valid temperament")
+    }
+
+    // C-G to A-E a quarter syntonic comma narrow, pure major third C-E,
+    // F#-C# a schisma narrow and every other fifth pure
+    pub fn kirnberger_iii(base: f32) -> Self {
+        let q = cents(SYNTONIC_COMMA) / 4.0;
+        Self::from_tempering(
+            "Kirnberger III",
+            0,
+            [q, q, q, q, 0.0, 0.0, cents(SCHISMA), 0.0, 0.0, 0.0, 0.0],
+            base,
+        )
+        .expect("Kirnberger III is a valid temperament")
+    }
+
+    // F-C to E-B a sixth Pythagorean comma narrow, the rest pure
+    pub fn vallotti(base: f32) -> Self {
+        let s = 1.0 / 6.0;
+        Self::from_comma_split(
+            "Vallotti",
+            0,
+            PYTHAGOREAN_COMMA,
+            [s, s, s, s, s, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+            base,
+        )
+        .expect("Vallotti is a valid temperament")
+    }
+
+    // like Vallotti a step further round the circle, C-G to B-F# a sixth comma narrow
+    pub fn young_ii(base: f32) -> Self {
+        let s = 1.0 / 6.0;
+        Self::from_comma_split(
+            "Young II",
+            0,
+            PYTHAGOREAN_COMMA,
+            [s, s, s, s, s, s, 0.0, 0.0, 0.0, 0.0, 0.0],
+            base,
+        )
+        .expect("Young II is a valid temperament")
+    }
+
+    // Eb to G# in equal fifths `fraction` of a syntonic comma narrow, the wolf on G#-Eb
+    pub fn meantone(fraction: f64, base: f32) -> Self {
+        Self::from_comma_split(
+            &format!("{:.3} comma meantone", fraction),
+            3,
+            SYNTONIC_COMMA,
+            [fraction; 11],
+            base,
+        )
+        .expect("meantone is a valid temperament")
+    }
+
+    // size of the fifth above every step in cents, the wolf stands out
+    pub fn fifths(&self) -> [f64; 12] {
+        let mut fifths = [0.0; 12];
+        for (step, fifth) in fifths.iter_mut().enumerate() {
+            *fifth = self.cents(step as i32 + 7) - self.cents(step as i32);
+        }
+        fifths
+    }
+
+    // and the major third above every step
+    pub fn thirds(&self) -> [f64; 12] {
+        let mut thirds = [0.0; 12];
+        for (step, third) in thirds.iter_mut().enumerate() {
+            *third = self.cents(step as i32 + 4) - self.cents(step as i32);
+        }
+        thirds
+    }
+}
+
+impl Tuning for Temperament {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn base(&self) -> f32 {
+        self.base
+    }
+
+    fn steps(&self) -> usize {
+        12
+    }
+
+    fn ratio(&self, step: usize) -> f64 {
+        self.ratios[step]
+    }
+}
+
+#[test]
+fn test_temperaments() {
+    let pure = cents(PURE_FIFTH);
+    for temperament in [
+        Temperament::werckmeister_iii(1.0),
+        Temperament::kirnberger_iii(1.0),
+        Temperament::vallotti(1.0),
+        Temperament::young_ii(1.0),
+    ]
+    .iter()
+    {
+        // well temperaments have no wolf
+        assert!(temperament
+            .fifths()
+            .iter()
+            .all(|fifth| *fifth <= pure + 1e-9 && *fifth > 690.0));
+    }
+    // the usual published tables, cents above C
+    let published = |temperament: &Temperament, cents: &[(i32, f64)]| {
+        for (step, expected) in cents.iter() {
+            let actual = temperament.cents(*step);
+            assert!((actual - expected).abs() < 0.01, "{} {}", step, actual);
+        }
+    };
+    published(
+        &Temperament::werckmeister_iii(1.0),
+        &[
+            (1, 90.225),
+            (2, 192.18),
+            (4, 390.225),
+            (6, 588.27),
+            (9, 888.27),
+            (11, 1092.18),
+        ],
+    );
+    published(
+        &Temperament::vallotti(1.0),
+        &[
+            (1, 94.135),
+            (4, 392.18),
+            (5, 501.955),
+            (7, 698.045),
+            (10, 1000.0),
+            (11, 1090.225),
+        ],
+    );
+    let kirnberger = Temperament::kirnberger_iii(1.0);
+    assert!((kirnberger.thirds()[0] - cents(5.0 / 4.0)).abs() < 1e-9);
+    let vallotti = Temperament::vallotti(1.0);
+    assert!((vallotti.fifths()[5] - (pure - cents(PYTHAGOREAN_COMMA) / 6.0)).abs() < 1e-9);
+
+    // quarter comma meantone has the same notes as `Meantone` and a wolf on G#
+    let meantone = Temperament::meantone(0.25, 1.0);
+    let reference = crate::tuning::Meantone::quarter_comma(1.0);
+    assert!((0..12).all(|step| (meantone.ratio(step) - reference.ratio(step)).abs() < 1e-12));
+    assert!(meantone.fifths()[8] > 735.0);
+
+    assert!(Temperament::from_fifths("flat", 0, [1.3; 11], 1.0).is_err());
+    assert!(Temperament::from_fifths("start", 12, [1.5; 11], 1.0).is_err());
+}
+
+pub fn run(context: AudioContext) -> Result<(), Box<dyn std::error::Error>> {
+    println!("20 :: rendering cadences in historical temperaments");
+    let c4 = ConcertPitch::default().frequency(Note::parse("C4")?);
+    let temperaments = [
+        Temperament::werckmeister_iii(c4),
+        Temperament::kirnberger_iii(c4),
+        Temperament::vallotti(c4),
+        Temperament::young_ii(c4),
+        Temperament::meantone(0.25, c4),
+    ];
+    for temperament in temperaments.iter() {
+        println!(
+            "{}, major thirds against equal temperament:",
+            temperament.name()
+        );
+        for (step, third) in temperament.thirds().iter().enumerate() {
+            print!("{} {:+.1}  ", NAMES[step], third - 400.0);
+        }
+        println!();
+        println!("fifths against pure 3/2:");
+        for (step, fifth) in temperament.fifths().iter().enumerate() {
+            print!("{} {:+.1}  ", NAMES[step], fifth - cents(PURE_FIFTH));
+        }
+        println!();
+    }
+
+    // I IV V I in C, then in F#, then barka, in every temperament
+    let envelope = Envelope::adsr(0.02, 0.2, 0.7, 0.2);
+    let chord_length = context.samples(0.8);
+    let release = context.samples(envelope.release_time());
+    let spec = context.wav_spec(1); // mono
+    let mut writer = hound::WavWriter::create(OUTPUT_FILE, spec)?;
+    for temperament in temperaments.iter() {
+        for key in [0, 6].iter() {
+            let cadence = vec![
+                vec![0, 4, 7],
+                vec![5, 9, 12],
+                vec![7, 11, 14],
+                vec![0, 4, 7],
+            ];
+            for notes in cadence {
+                let notes = notes.into_iter().map(|note| note + key - 12).collect();
+                let voice = chord(context, temperament, notes, &Waveform::Saw);
+                for sample in envelope
+                    .note(context, voice, chord_length - release)
+                    .chain(std::iter::repeat(0.0))
+                    .take(chord_length)
+                {
+                    writer.write_sample(i16::from_synth(sample / 4.0))?;
+                }
+            }
+        }
+        for sample in make_barka::<i16>(context, temperament, &Waveform::Sine)? {
+            writer.write_sample(sample)?;
+        }
+    }
+    Ok(())
+}
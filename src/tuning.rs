@@ -0,0 +1,359 @@
+pub const A4: f32 = 440.0;
+
+// maps note indices to frequencies, index 0 is `base()` and every `steps()`
+// indices the scale repeats one `period()` higher
+pub trait Tuning {
+    fn name(&self) -> String;
+
+    // frequency of note 0
+    fn base(&self) -> f32;
+
+    fn steps(&self) -> usize;
+
+    // ratio of `step` (0 - steps) above the start of its period
+    fn ratio(&self, step: usize) -> f64;
+
+    // the interval the scale repeats at, an octave for almost every tuning
+    fn period(&self) -> f64 {
+        2.0
+    }
+
+    // any index, negative ones go below `base()`
+    fn frequency(&self, index: i32) -> f32 {
+        let steps = self.steps() as i32;
+        let (period, step) = (index.div_euclid(steps), index.rem_euclid(steps));
+        (self.base() as f64 * self.ratio(step as usize) * self.period().powi(period)) as f32
+    }
+
+    // cents above `base()`, exact where `frequency` rounds to f32
+    fn cents(&self, index: i32) -> f64 {
+        let steps = self.steps() as i32;
+        let (period, step) = (index.div_euclid(steps), index.rem_euclid(steps));
+        1200.0 * (self.ratio(step as usize).log2() + period as f64 * self.period().log2())
+    }
+
+    fn notes(&self, count: usize) -> Vec<f32> {
+        (0..count as i32)
+            .map(|index| self.frequency(index))
+            .collect()
+    }
+}
+
+// 12 pure fifths in a chain from `lowest` fifths below the tonic to 11 above that,
+// the fifth that would close the circle is a Pythagorean comma short, the wolf
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pythagorean {
+    pub base: f32,
+    pub lowest: i32,
+}
+
+impl Pythagorean {
+    // five fifths down and six up, 256/243 for the minor second and 729/512
+    // rather than 1024/729 for the tritone
+    pub fn new(base: f32) -> Self {
+        Self::with_chain(base, -5)
+    }
+
+    pub fn with_chain(base: f32, lowest: i32) -> Self {
+        Self { base, lowest }
+    }
+
+    pub fn highest(&self) -> i32 {
+        self.lowest + 11
+    }
+
+    // 3^fifths octave reduced, as one division so that it rounds like `729.0 / 512.0`
+    fn fifths_ratio(fifths: i32) -> f64 {
+        let power = 3.0f64.powi(fifths.abs());
+        let octaves = 2.0f64.powi(power.log2().floor() as i32);
+        match fifths {
+            fifths if fifths >= 0 => power / octaves,
+            _ => (octaves * 2.0) / power,
+        }
+    }
+
+    fn step_of(fifths: i32) -> usize {
+        (fifths * 7).rem_euclid(12) as usize
+    }
+
+    // step the wolf fifth starts on, it lands on the bottom of the chain
+    // a Pythagorean comma narrow
+    pub fn wolf(&self) -> usize {
+        Self::step_of(self.highest())
+    }
+
+    // step that is spelled from the top of the chain, its enharmonic twin one fifth below
+    // the bottom would be a Pythagorean comma lower, e.g. F# at 729/512 and not Gb at 1024/729
+    pub fn comma(&self) -> (usize, f64, f64) {
+        let step = Self::step_of(self.highest());
+        (
+            step,
+            Self::fifths_ratio(self.highest()),
+            Self::fifths_ratio(self.lowest - 1),
+        )
+    }
+
+    // pairs of notes that sound the wolf fifth, or its inversion, against each other
+    pub fn wolf_intervals(&self, notes: &[i32]) -> Vec<(i32, i32)> {
+        let (low, high) = (self.wolf() as i32, Self::step_of(self.lowest) as i32);
+        let mut pairs = vec![];
+        for (index, one) in notes.iter().enumerate() {
+            for other in notes[index + 1..].iter() {
+                let steps = (one.rem_euclid(12), other.rem_euclid(12));
+                if steps == (low, high) || steps == (high, low) {
+                    pairs.push((*one.min(other), *one.max(other)));
+                }
+            }
+        }
+        pairs.sort_unstable();
+        pairs.dedup();
+        pairs
+    }
+
+    // the chain closest to the usual one that keeps the wolf out of `notes`
+    pub fn avoiding(base: f32, notes: &[i32]) -> Option<Self> {
+        (-11..=0)
+            .map(|lowest| Self::with_chain(base, lowest))
+            .filter(|tuning| tuning.wolf_intervals(notes).is_empty())
+            .min_by_key(|tuning| (tuning.lowest + 5).abs())
+    }
+}
+
+impl Tuning for Pythagorean {
+    fn name(&self) -> String {
+        "Pythagorean".to_string()
+    }
+
+    fn base(&self) -> f32 {
+        self.base
+    }
+
+    fn steps(&self) -> usize {
+        12
+    }
+
+    fn ratio(&self, step: usize) -> f64 {
+        let fifths = (self.lowest..=self.highest())
+            .find(|fifths| Self::step_of(*fifths) == step)
+            .unwrap_or(0);
+        Self::fifths_ratio(fifths)
+    }
+}
+
+// equal division of the octave into `steps` steps
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Edo {
+    pub steps: usize,
+    pub base: f32,
+}
+
+impl Edo {
+    pub fn new(steps: usize, base: f32) -> Self {
+        Self {
+            steps: steps.max(1),
+            base,
+        }
+    }
+
+    pub fn twelve(base: f32) -> Self {
+        Self::new(12, base)
+    }
+}
+
+impl Tuning for Edo {
+    fn name(&self) -> String {
+        format!("{}-EDO", self.steps)
+    }
+
+    fn base(&self) -> f32 {
+        self.base
+    }
+
+    fn steps(&self) -> usize {
+        self.steps
+    }
+
+    fn ratio(&self, step: usize) -> f64 {
+        2.0f64.powf(step as f64 / self.steps as f64)
+    }
+}
+
+// 5-limit just intonation, every interval a ratio of 2, 3 and 5
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JustIntonation {
+    pub base: f32,
+}
+
+impl JustIntonation {
+    const OFFSETS: [f64; 12] = [
+        1.0,
+        16.0 / 15.0,
+        9.0 / 8.0,
+        6.0 / 5.0,
+        5.0 / 4.0,
+        4.0 / 3.0,
+        45.0 / 32.0,
+        3.0 / 2.0,
+        8.0 / 5.0,
+        5.0 / 3.0,
+        9.0 / 5.0,
+        15.0 / 8.0,
+    ];
+
+    pub fn new(base: f32) -> Self {
+        Self { base }
+    }
+}
+
+impl Tuning for JustIntonation {
+    fn name(&self) -> String {
+        "5-limit just intonation".to_string()
+    }
+
+    fn base(&self) -> f32 {
+        self.base
+    }
+
+    fn steps(&self) -> usize {
+        Self::OFFSETS.len()
+    }
+
+    fn ratio(&self, step: usize) -> f64 {
+        Self::OFFSETS[step]
+    }
+}
+
+// 12 notes from a chain of equal fifths, three flats to eight sharps of the tonic
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Meantone {
+    pub fifth: f64,
+    pub base: f32,
+}
+
+impl Meantone {
+    pub fn new(fifth: f64, base: f32) -> Self {
+        Self { fifth, base }
+    }
+
+    // fifths a quarter of a syntonic comma narrow, pure major thirds
+    pub fn quarter_comma(base: f32) -> Self {
+        Self::new(5.0f64.powf(0.25), base)
+    }
+}
+
+impl Tuning for Meantone {
+    fn name(&self) -> String {
+        format!("meantone, {:.2} cent fifths", 1200.0 * self.fifth.log2())
+    }
+
+    fn base(&self) -> f32 {
+        self.base
+    }
+
+    fn steps(&self) -> usize {
+        12
+    }
+
+    fn ratio(&self, step: usize) -> f64 {
+        // the fifth that lands on `step`, seven semitones at a time
+        let fifths = (-3..=8)
+            .find(|fifths: &i32| (fifths * 7).rem_euclid(12) as usize == step)
+            .unwrap_or(0);
+        let ratio = self.fifth.powi(fifths);
+        ratio / 2.0f64.powf(ratio.log2().floor())
+    }
+}
+
+// any list of ratios, starting at 1/1 and going up within the period
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ratios {
+    name: String,
+    ratios: Vec<f64>,
+    period: f64,
+    pub base: f32,
+}
+
+impl Ratios {
+    pub fn new(
+        name: &str,
+        ratios: Vec<f64>,
+        period: f64,
+        base: f32,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if ratios.first() != Some(&1.0) {
+            return Err("a ratio list has to start at 1/1".into());
+        }
+        if period <= 1.0 {
+            return Err(format!("period {} is not above 1/1", period).into());
+        }
+        if let Some(index) = (1..ratios.len())
+            .find(|index| ratios[*index] <= ratios[index - 1] || ratios[*index] >= period)
+        {
+            return Err(format!(
+                "ratio {} ({}) does not go up or leaves the period",
+                index, ratios[index]
+            )
+            .into());
+        }
+        Ok(Self {
+            name: name.to_string(),
+            ratios,
+            period,
+            base,
+        })
+    }
+}
+
+impl Tuning for Ratios {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn base(&self) -> f32 {
+        self.base
+    }
+
+    fn steps(&self) -> usize {
+        self.ratios.len()
+    }
+
+    fn ratio(&self, step: usize) -> f64 {
+        self.ratios[step]
+    }
+
+    fn period(&self) -> f64 {
+        self.period
+    }
+}
+
+#[test]
+fn test_tunings() {
+    let pythagorean = Pythagorean::new(220.0);
+    assert_eq!(pythagorean.frequency(7), 330.0);
+    assert_eq!(pythagorean.frequency(-12), 110.0);
+    assert_eq!(pythagorean.frequency(19), 660.0);
+    assert_eq!(pythagorean.ratio(1), 256.0 / 243.0);
+    assert_eq!(pythagorean.ratio(6), 729.0 / 512.0);
+    assert_eq!(pythagorean.ratio(11), 243.0 / 128.0);
+    assert_eq!(pythagorean.wolf(), 6); // D# up to Bb over A
+    assert_eq!(pythagorean.comma(), (6, 729.0 / 512.0, 1024.0 / 729.0));
+    assert_eq!(
+        pythagorean.wolf_intervals(&[0, 1, 6, 13]),
+        vec![(1, 6), (6, 13)]
+    );
+    let flat = Pythagorean::with_chain(220.0, -6);
+    assert_eq!((flat.ratio(6), flat.wolf()), (1024.0 / 729.0, 11));
+    let avoiding = Pythagorean::avoiding(220.0, &[1, 6]).unwrap();
+    assert!(avoiding.wolf_intervals(&[1, 6]).is_empty());
+    assert_eq!((avoiding.lowest + 5).abs(), 1);
+    assert!((Edo::twelve(220.0).frequency(3) - 261.6256).abs() < 1e-3);
+    assert_eq!(Edo::new(19, 220.0).frequency(38), 880.0);
+    let meantone = Meantone::quarter_comma(220.0);
+    assert!((meantone.ratio(4) - 1.25).abs() < 1e-12);
+    assert!((meantone.ratio(3) - 1.2).abs() > 1e-3); // the flat side is not just
+    assert_eq!(JustIntonation::new(220.0).frequency(4), 275.0);
+    let tritave = Ratios::new("tritave", vec![1.0, 1.5], 3.0, 100.0).unwrap();
+    assert_eq!(tritave.frequency(3), 450.0);
+    assert!(Ratios::new("bad", vec![1.0, 1.5, 1.4], 2.0, 100.0).is_err());
+    assert!(Ratios::new("bad", vec![1.5], 2.0, 100.0).is_err());
+}
@@ -0,0 +1,318 @@
+use std::path::Path;
+
+use plotters::prelude::*;
+
+use crate::pitch::cents;
+use crate::tuning::{Edo, Pythagorean, Tuning, A4};
+
+const OUTPUT_TEXT: &str = "./output/tuning_report.txt";
+const OUTPUT_CSV: &str = "./output/tuning_report.csv";
+const OUTPUT_CHART: &str = "./output/tuning_report.png";
+
+const INTERVAL_NAMES: [&str; 13] = [
+    "unison",
+    "minor second",
+    "major second",
+    "minor third",
+    "major third",
+    "fourth",
+    "tritone",
+    "fifth",
+    "minor sixth",
+    "major sixth",
+    "minor seventh",
+    "major seventh",
+    "octave",
+];
+
+const COMMAS: [(&str, f64, f64); 5] = [
+    ("Pythagorean comma", 531441.0, 524288.0),
+    ("syntonic comma", 81.0, 80.0),
+    ("schisma", 32805.0, 32768.0),
+    ("diesis", 128.0, 125.0),
+    ("septimal comma", 64.0, 63.0),
+];
+
+// how far a just interval may be from the tempered one to still count as the same
+const JUST_TOLERANCE: f64 = 25.0;
+
+// simplest ratio of whole numbers up to 16 within `JUST_TOLERANCE` cents
+fn nearest_just(interval: f64) -> Option<(u64, u64)> {
+    (1..=16u64)
+        .flat_map(|denominator| (denominator..=32).map(move |numerator| (numerator, denominator)))
+        .filter(|(numerator, denominator)| {
+            (cents(*numerator as f64 / *denominator as f64) - interval).abs() < JUST_TOLERANCE
+        })
+        .min_by_key(|(numerator, denominator)| numerator * denominator)
+}
+
+// `difference` cents as a simple fraction of one of the `COMMAS`, e.g. `1/12 Pythagorean comma`
+fn comma_fraction(difference: f64) -> Option<String> {
+    if difference.abs() < 0.01 {
+        return None;
+    }
+    (1..=12)
+        .flat_map(|denominator| COMMAS.iter().map(move |comma| (denominator, comma)))
+        .find_map(|(denominator, (name, numerator, comma_denominator))| {
+            let comma = cents(numerator / comma_denominator);
+            let multiple = (difference * denominator as f64 / comma).round();
+            // the schisma is only 0.002 cents off 1/12 Pythagorean comma
+            let error = (multiple * comma / denominator as f64 - difference).abs();
+            if error < 1e-4 && multiple != 0.0 {
+                Some(match (multiple as i64, denominator) {
+                    (multiple, 1) => format!("{} {}", multiple, name),
+                    (multiple, denominator) => format!("{}/{} {}", multiple, denominator, name),
+                })
+            } else {
+                None
+            }
+        })
+}
+
+// index of `tuning` that is closest to `interval` cents above its base
+fn nearest_index(tuning: &impl Tuning, interval: f64) -> i32 {
+    let steps = tuning.steps() as i32;
+    let distance = |index: &i32| ((tuning.cents(*index) - interval).abs() * 1000.0) as i64;
+    (-steps..=3 * steps).min_by_key(distance).unwrap_or(0)
+}
+
+// beating between the partials that meet in a just interval `numerator/denominator`
+fn beats(low: f32, high: f32, just: Option<(u64, u64)>) -> Option<f32> {
+    just.map(|(numerator, denominator)| (denominator as f32 * high - numerator as f32 * low).abs())
+}
+
+// one interval, `steps` steps of the first tuning above its step `root`
+#[derive(Debug, Clone, PartialEq)]
+pub struct IntervalComparison {
+    pub root: usize,
+    pub steps: usize,
+    pub name: String,
+    pub one: f64,
+    pub other: f64,
+    pub just: Option<(u64, u64)>,
+    pub one_beats: Option<f32>,
+    pub other_beats: Option<f32>,
+    pub comma: Option<String>,
+}
+
+impl IntervalComparison {
+    // cents the second tuning is wider by
+    pub fn difference(&self) -> f64 {
+        self.other - self.one
+    }
+}
+
+// every interval of one tuning next to the same interval in another,
+// tunings with a different number of steps get compared to the closest note
+#[derive(Debug, Clone, PartialEq)]
+pub struct TuningReport {
+    pub one: String,
+    pub other: String,
+    pub intervals: Vec<IntervalComparison>,
+}
+
+impl TuningReport {
+    pub fn new(one: &impl Tuning, other: &impl Tuning) -> Self {
+        let steps = one.steps();
+        let same_steps = steps == other.steps();
+        let other_index = |index: i32| {
+            if same_steps {
+                index
+            } else {
+                nearest_index(other, one.cents(index))
+            }
+        };
+        let intervals = (0..steps)
+            .flat_map(|root| (0..=steps).map(move |size| (root, size)))
+            .map(|(root, size)| {
+                let (low, high) = (root as i32, (root + size) as i32);
+                let (other_low, other_high) = (other_index(low), other_index(high));
+                let one_cents = one.cents(high) - one.cents(low);
+                let other_cents = other.cents(other_high) - other.cents(other_low);
+                let just = nearest_just(one_cents);
+                IntervalComparison {
+                    root,
+                    steps: size,
+                    name: match steps {
+                        12 => INTERVAL_NAMES[size].to_string(),
+                        _ => format!("{} steps", size),
+                    },
+                    one: one_cents,
+                    other: other_cents,
+                    just,
+                    one_beats: beats(one.frequency(low), one.frequency(high), just),
+                    other_beats: beats(
+                        other.frequency(other_low),
+                        other.frequency(other_high),
+                        just,
+                    ),
+                    comma: comma_fraction(other_cents - one_cents),
+                }
+            })
+            .collect();
+        Self {
+            one: one.name(),
+            other: other.name(),
+            intervals,
+        }
+    }
+
+    // intervals above the first note of the scale
+    pub fn above_tonic(&self) -> impl Iterator<Item = &IntervalComparison> {
+        self.intervals.iter().filter(|interval| interval.root == 0)
+    }
+
+    pub fn to_text(&self) -> String {
+        let format_beats = |beats: Option<f32>| match beats {
+            Some(beats) => format!("{:.2}", beats),
+            None => "-".to_string(),
+        };
+        let mut lines = vec![
+            format!("{} against {}", self.one, self.other),
+            format!(
+                "{:>4}  {:<14}  {:>9}  {:>9}  {:>7}  {:>5}  {:>8}  {:>8}  {}",
+                "root", "interval", "one", "other", "diff", "just", "beats", "beats", "comma"
+            ),
+        ];
+        lines.extend(self.intervals.iter().map(|interval| {
+            format!(
+                "{:>4}  {:<14}  {:>9.3}  {:>9.3}  {:>+7.3}  {:>5}  {:>8}  {:>8}  {}",
+                interval.root,
+                interval.name,
+                interval.one,
+                interval.other,
+                interval.difference(),
+                interval
+                    .just
+                    .map_or("-".to_string(), |(p, q)| format!("{}/{}", p, q)),
+                format_beats(interval.one_beats),
+                format_beats(interval.other_beats),
+                interval.comma.clone().unwrap_or_default()
+            )
+        }));
+        lines.join("\n") + "\n"
+    }
+
+    pub fn to_csv(&self) -> String {
+        let optional = |value: Option<f32>| value.map_or(String::new(), |v| v.to_string());
+        let mut lines = vec![
+            "root,steps,interval,one_cents,other_cents,difference_cents,just,one_beats_hz,other_beats_hz,comma"
+                .to_string(),
+        ];
+        lines.extend(self.intervals.iter().map(|interval| {
+            format!(
+                "{},{},{},{},{},{},{},{},{},{}",
+                interval.root,
+                interval.steps,
+                interval.name,
+                interval.one,
+                interval.other,
+                interval.difference(),
+                interval
+                    .just
+                    .map_or(String::new(), |(p, q)| format!("{}/{}", p, q)),
+                optional(interval.one_beats),
+                optional(interval.other_beats),
+                interval.comma.clone().unwrap_or_default()
+            )
+        }));
+        lines.join("\n") + "\n"
+    }
+
+    // cents difference of every interval, one dot per root, the line follows the tonic
+    pub fn plot<T: AsRef<Path>>(&self, path: T) -> Result<(), Box<dyn std::error::Error>> {
+        let steps = self
+            .intervals
+            .iter()
+            .map(|interval| interval.steps)
+            .max()
+            .unwrap_or(0);
+        let (min, max) = self
+            .intervals
+            .iter()
+            .map(|interval| interval.difference() as f32)
+            .fold((-1.0f32, 1.0f32), |(min, max), v| (min.min(v), max.max(v)));
+        let root = BitMapBackend::new(path.as_ref(), (1920, 1080)).into_drawing_area();
+        root.fill(&WHITE)?;
+        let mut chart = ChartBuilder::on(&root)
+            .caption(
+                format!("{} against {}", self.other, self.one),
+                ("sans-serif", 40).into_font(),
+            )
+            .margin(20)
+            .x_label_area_size(50)
+            .y_label_area_size(80)
+            .build_cartesian_2d(-0.5f32..(steps as f32 + 0.5), (min * 1.1)..(max * 1.1))?;
+        chart
+            .configure_mesh()
+            .x_desc("interval in steps")
+            .y_desc("cents wider")
+            .draw()?;
+        chart
+            .draw_series(self.intervals.iter().map(|interval| {
+                Circle::new(
+                    (interval.steps as f32, interval.difference() as f32),
+                    4,
+                    BLUE.mix(0.3).filled(),
+                )
+            }))?
+            .label("from every root")
+            .legend(|(x, y)| Circle::new((x, y), 4, BLUE.mix(0.3).filled()));
+        chart
+            .draw_series(LineSeries::new(
+                self.above_tonic()
+                    .map(|interval| (interval.steps as f32, interval.difference() as f32)),
+                &RED,
+            ))?
+            .label("from the tonic")
+            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], RED));
+        chart
+            .configure_series_labels()
+            .background_style(WHITE.mix(0.8))
+            .border_style(BLACK)
+            .draw()?;
+        root.present()?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_tuning_report() {
+    let report = TuningReport::new(&Pythagorean::new(220.0), &Edo::twelve(220.0));
+    assert_eq!(report.intervals.len(), 12 * 13);
+    let fifth = &report.intervals[7];
+    assert_eq!((fifth.root, fifth.name.as_str()), (0, "fifth"));
+    assert!((fifth.difference() + 1.955).abs() < 1e-3);
+    assert_eq!(fifth.just, Some((3, 2)));
+    assert!(fifth.one_beats.unwrap() < 1e-3);
+    // 220 Hz and 329.63 Hz, the third partial against the second
+    assert!((fifth.other_beats.unwrap() - 0.75).abs() < 0.01);
+    assert_eq!(fifth.comma.as_deref(), Some("-1/12 Pythagorean comma"));
+    // the pythagorean major third is a syntonic comma wider than 5/4
+    let third = &report.intervals[4];
+    assert_eq!(third.just, Some((5, 4)));
+    assert!((third.one - cents(5.0 / 4.0) - cents(81.0 / 80.0)).abs() < 1e-3);
+    // a tuning with a different number of steps is compared note by note
+    let report = TuningReport::new(&Edo::twelve(220.0), &Edo::new(24, 220.0));
+    assert!(report
+        .intervals
+        .iter()
+        .all(|interval| interval.difference().abs() < 1e-3));
+}
+
+pub fn run() -> Result<(), Box<dyn std::error::Error>> {
+    println!("19 :: comparing pythagorean tuning with equal temperament");
+    let report = TuningReport::new(&Pythagorean::new(A4 * 0.5), &Edo::twelve(A4 * 0.5));
+    for interval in report.above_tonic() {
+        println!(
+            "{:<14} {:>+7.3} cents {}",
+            interval.name,
+            interval.difference(),
+            interval.comma.clone().unwrap_or_default()
+        );
+    }
+    std::fs::write(OUTPUT_TEXT, report.to_text())?;
+    std::fs::write(OUTPUT_CSV, report.to_csv())?;
+    report.plot(OUTPUT_CHART)?;
+    Ok(())
+}
@@ -0,0 +1,307 @@
+use crate::context::AudioContext;
+use crate::envelope::{Envelope, EnvelopeGenerator};
+use crate::oscillators::{Instrument, Waveform};
+use crate::sample::Sample;
+use crate::tuning::{Edo, Tuning, A4};
+
+const OUTPUT_FILE: &str = "./output/voices.wav";
+const OLDEST_FILE: &str = "./output/voices_oldest.wav";
+const QUIETEST_FILE: &str = "./output/voices_quietest.wav";
+const STEAL_FADE: f32 = 0.005; // seconds, stolen voices fade out this fast instead of clicking
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Message {
+    NoteOn { key: i32, freq: f32, velocity: f32 },
+    NoteOff { key: i32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Event {
+    pub time: usize, // in samples
+    pub message: Message,
+}
+
+impl Event {
+    pub fn note_on(time: usize, key: i32, freq: f32, velocity: f32) -> Self {
+        Self {
+            time,
+            message: Message::NoteOn {
+                key,
+                freq,
+                velocity,
+            },
+        }
+    }
+
+    pub fn note_off(time: usize, key: i32) -> Self {
+        Self {
+            time,
+            message: Message::NoteOff { key },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stealing {
+    Oldest,
+    Quietest,
+    SameNote, // a repeated key takes over its own voice, otherwise the oldest one goes
+}
+
+struct Voice {
+    key: i32,
+    started: usize,
+    velocity: f32,
+    source: Box<dyn Iterator<Item = f32>>,
+    envelope: EnvelopeGenerator,
+}
+
+impl Voice {
+    fn released(&self) -> bool {
+        !self.envelope.is_gated()
+    }
+
+    fn loudness(&self) -> f32 {
+        self.envelope.level() * self.velocity
+    }
+}
+
+// a voice that got stolen, ramping down to silence
+struct Fading {
+    voice: Voice,
+    gain: f32,
+}
+
+pub struct VoiceManager<I: Instrument> {
+    context: AudioContext,
+    instrument: I,
+    envelope: Envelope,
+    max_voices: usize,
+    stealing: Stealing,
+    voices: Vec<Voice>,
+    fading: Vec<Fading>,
+}
+
+impl<I: Instrument> VoiceManager<I> {
+    pub fn new(
+        context: AudioContext,
+        instrument: I,
+        envelope: Envelope,
+        max_voices: usize,
+        stealing: Stealing,
+    ) -> Self {
+        Self {
+            context,
+            instrument,
+            envelope,
+            max_voices: max_voices.max(1),
+            stealing,
+            voices: vec![],
+            fading: vec![],
+        }
+    }
+
+    // released voices are always stolen first, they are on their way out anyway
+    fn victim(&self, key: i32) -> usize {
+        let candidates = || {
+            let released = self.voices.iter().any(Voice::released);
+            self.voices
+                .iter()
+                .enumerate()
+                .filter(move |(_, voice)| voice.released() || !released)
+        };
+        let same_note = candidates().find(|(_, voice)| voice.key == key);
+        let chosen = match self.stealing {
+            Stealing::SameNote if same_note.is_some() => same_note,
+            Stealing::Quietest => candidates().min_by(|(_, one), (_, other)| {
+                one.loudness()
+                    .partial_cmp(&other.loudness())
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            }),
+            Stealing::Oldest | Stealing::SameNote => {
+                candidates().min_by_key(|(_, voice)| voice.started)
+            }
+        };
+        chosen.map(|(index, _)| index).unwrap_or(0)
+    }
+
+    pub fn note_on(&mut self, time: usize, key: i32, freq: f32, velocity: f32) {
+        let same_note = self.voices.iter().position(|voice| voice.key == key);
+        let steal = match (self.stealing, same_note) {
+            (Stealing::SameNote, Some(index)) => Some(index),
+            _ if self.voices.len() >= self.max_voices => Some(self.victim(key)),
+            _ => None,
+        };
+        if let Some(index) = steal {
+            let voice = self.voices.remove(index);
+            self.fading.push(Fading { voice, gain: 1.0 });
+        }
+        let mut envelope = self.envelope.generator(self.context);
+        envelope.gate_on();
+        self.voices.push(Voice {
+            key,
+            started: time,
+            velocity,
+            source: self.instrument.voice(self.context, freq),
+            envelope,
+        });
+    }
+
+    pub fn note_off(&mut self, key: i32) {
+        self.voices
+            .iter_mut()
+            .filter(|voice| voice.key == key)
+            .for_each(|voice| voice.envelope.gate_off());
+    }
+
+    pub fn is_silent(&self) -> bool {
+        self.voices.is_empty() && self.fading.is_empty()
+    }
+
+    // mixes one sample of every sounding voice, dropping the ones that finished
+    pub fn next_sample(&mut self) -> f32 {
+        let mut mix = 0.0;
+        for voice in self.voices.iter_mut() {
+            let level = voice.envelope.next().unwrap_or(0.0);
+            mix += voice.source.next().unwrap_or(0.0) * level * voice.velocity;
+        }
+        let step = 1.0 / (STEAL_FADE * self.context.rate());
+        for fading in self.fading.iter_mut() {
+            let level = fading.voice.envelope.next().unwrap_or(0.0);
+            let sample = fading.voice.source.next().unwrap_or(0.0);
+            mix += sample * level * fading.voice.velocity * fading.gain;
+            fading.gain -= step;
+        }
+        self.voices.retain(|voice| !voice.envelope.is_finished());
+        self.fading.retain(|fading| fading.gain > 0.0);
+        mix
+    }
+
+    // plays the events and keeps going until the last release tail is over
+    pub fn render(self, mut events: Vec<Event>) -> Performance<I> {
+        events.sort_by_key(|event| event.time);
+        Performance {
+            manager: self,
+            events: events.into_iter().peekable(),
+            time: 0,
+        }
+    }
+}
+
+pub struct Performance<I: Instrument> {
+    manager: VoiceManager<I>,
+    events: std::iter::Peekable<std::vec::IntoIter<Event>>,
+    time: usize,
+}
+
+impl<I: Instrument> Iterator for Performance<I> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(event) = self.events.peek() {
+            if event.time > self.time {
+                break;
+            }
+            match event.message {
+                Message::NoteOn {
+                    key,
+                    freq,
+                    velocity,
+                } => self.manager.note_on(self.time, key, freq, velocity),
+                Message::NoteOff { key } => self.manager.note_off(key),
+            }
+            self.events.next();
+        }
+        if self.events.peek().is_none() && self.manager.is_silent() {
+            return None;
+        }
+        self.time += 1;
+        Some(self.manager.next_sample())
+    }
+}
+
+#[test]
+fn test_voice_stealing() {
+    let envelope = Envelope::ar(0.0, 0.1);
+    let mut manager = VoiceManager::new(
+        AudioContext::default(),
+        Waveform::Sine,
+        envelope,
+        2,
+        Stealing::Oldest,
+    );
+    manager.note_on(0, 60, 261.6, 1.0);
+    manager.note_on(1, 64, 329.6, 1.0);
+    manager.note_on(2, 67, 392.0, 1.0);
+    assert_eq!(manager.voices.len(), 2);
+    assert!(manager.voices.iter().all(|voice| voice.key != 60));
+
+    // released voices go first, even when they are not the oldest
+    manager.note_off(67);
+    manager.next_sample();
+    manager.note_on(3, 72, 523.2, 1.0);
+    assert!(manager.voices.iter().any(|voice| voice.key == 64));
+    assert!(manager.voices.iter().all(|voice| voice.key != 67));
+}
+
+pub fn run(context: AudioContext) -> Result<(), Box<dyn std::error::Error>> {
+    println!("11 :: rendering overlapping notes with a voice allocator");
+    let spec = context.wav_spec(1); // mono
+    let mut writer = hound::WavWriter::create(OUTPUT_FILE, spec)?;
+    let tuning = Edo::twelve(A4 * 0.5);
+    let beat = context.samples(0.5);
+    let mut events = vec![];
+    // sustained chords, two bars each
+    for (bar, chord) in [[0, 4, 7], [5, 9, 12], [7, 11, 14], [0, 4, 7]]
+        .iter()
+        .enumerate()
+    {
+        for key in chord.iter() {
+            let freq = tuning.frequency(*key);
+            events.push(Event::note_on(bar * 4 * beat, *key, freq, 0.2));
+            events.push(Event::note_off((bar + 1) * 4 * beat - beat / 4, *key));
+        }
+    }
+    // a melody an octave up, every note rings over the next one
+    for (index, key) in [
+        12, 16, 19, 17, 21, 24, 23, 19, 26, 24, 23, 21, 19, 16, 12, 12,
+    ]
+    .iter()
+    .enumerate()
+    {
+        let freq = tuning.frequency(*key);
+        events.push(Event::note_on(index * beat, 100 + key, freq, 0.3));
+        events.push(Event::note_off(index * beat + beat * 3 / 2, 100 + key));
+    }
+    let manager = VoiceManager::new(
+        context,
+        Waveform::Triangle,
+        Envelope::adsr(0.02, 0.3, 0.7, 0.6),
+        8,
+        Stealing::SameNote,
+    );
+    for sample in manager.render(events.clone()) {
+        writer.write_sample(i16::from_synth(sample))?;
+    }
+
+    // four voices are not enough, the chords lose notes to the melody
+    for (stealing, path) in [
+        (Stealing::Oldest, OLDEST_FILE),
+        (Stealing::Quietest, QUIETEST_FILE),
+    ]
+    .iter()
+    {
+        let mut writer = hound::WavWriter::create(path, spec)?;
+        let manager = VoiceManager::new(
+            context,
+            Waveform::Triangle,
+            Envelope::adsr(0.02, 0.3, 0.7, 0.6),
+            4,
+            *stealing,
+        );
+        for sample in manager.render(events.clone()) {
+            writer.write_sample(i16::from_synth(sample))?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,232 @@
+use std::sync::Arc;
+
+use rustfft::{num_complex::Complex, FftPlanner};
+
+use crate::context::AudioContext;
+use crate::oscillators::{Instrument, Phasor};
+use crate::pythagorean_chords::{chord, AMPLITUDE};
+use crate::sample::{read_wav, Sample};
+use crate::tuning::JustIntonation;
+
+const TABLE_SIZE: usize = 2048;
+const MIP_LEVELS: usize = 10; // every level halves the number of harmonics
+const OUTPUT_FILE: &str = "./output/wavetable.wav";
+
+// one frame of the table, band-limited copies of a single cycle, one per octave
+type MipMap = Vec<Vec<f32>>;
+
+#[derive(Clone)]
+pub struct Wavetable {
+    frames: Arc<Vec<MipMap>>,
+}
+
+// linear interpolation between neighbouring samples of a cycle, wrapping around
+fn read_cycle(cycle: &[f32], position: f32) -> f32 {
+    let index = position.floor() as usize % cycle.len();
+    let next = (index + 1) % cycle.len();
+    let fraction = position - position.floor();
+    cycle[index] + (cycle[next] - cycle[index]) * fraction
+}
+
+// first channel of any WAV file as -1.0 - 1.0 floats
+pub fn read_first_channel<T: AsRef<std::path::Path>>(
+    path: T,
+) -> Result<(Vec<f32>, hound::WavSpec), Box<dyn std::error::Error>> {
+    let (samples, spec) = read_wav::<f32, _>(path)?;
+    let samples = samples
+        .into_iter()
+        .step_by(spec.channels as usize)
+        .collect();
+    Ok((samples, spec))
+}
+
+fn resample_cycle(cycle: &[f32]) -> Vec<f32> {
+    let step = cycle.len() as f32 / TABLE_SIZE as f32;
+    (0..TABLE_SIZE)
+        .map(|index| read_cycle(cycle, index as f32 * step))
+        .collect()
+}
+
+fn mip_map(cycle: &[f32], planner: &mut FftPlanner<f32>) -> MipMap {
+    let forward = planner.plan_fft_forward(TABLE_SIZE);
+    let inverse = planner.plan_fft_inverse(TABLE_SIZE);
+    let mut spectrum = cycle
+        .iter()
+        .map(|v| Complex { re: *v, im: 0.0f32 })
+        .collect::<Vec<_>>();
+    forward.process(&mut spectrum);
+    spectrum[0] = Complex { re: 0.0, im: 0.0 }; // no DC offset
+
+    (0..MIP_LEVELS)
+        .map(|level| {
+            let harmonics = (TABLE_SIZE / 2) >> level;
+            let mut band_limited = spectrum
+                .iter()
+                .enumerate()
+                .map(|(bin, c)| {
+                    let audible = bin <= harmonics || bin >= TABLE_SIZE - harmonics;
+                    if audible {
+                        *c
+                    } else {
+                        Complex { re: 0.0, im: 0.0 }
+                    }
+                })
+                .collect::<Vec<_>>();
+            inverse.process(&mut band_limited);
+            band_limited
+                .into_iter()
+                .map(|c| c.re / TABLE_SIZE as f32)
+                .collect()
+        })
+        .collect()
+}
+
+impl Wavetable {
+    // every frame is a single cycle of any length, it gets resampled to `TABLE_SIZE`
+    pub fn from_frames(frames: Vec<Vec<f32>>) -> Result<Self, Box<dyn std::error::Error>> {
+        if frames.is_empty() || frames.iter().any(|frame| frame.is_empty()) {
+            return Err("wavetable needs at least one non-empty frame".into());
+        }
+        let mut planner = FftPlanner::new();
+        let mut frames = frames
+            .iter()
+            .map(|frame| mip_map(&resample_cycle(frame), &mut planner))
+            .collect::<Vec<_>>();
+        // normalize the whole table at once so that morphing keeps relative levels
+        let peak = frames
+            .iter()
+            .flat_map(|mip_map| mip_map[0].iter())
+            .fold(0.0f32, |peak, v| peak.max(v.abs()));
+        if peak > 0.0 {
+            frames
+                .iter_mut()
+                .flat_map(|mip_map| mip_map.iter_mut())
+                .flat_map(|cycle| cycle.iter_mut())
+                .for_each(|v| *v /= peak);
+        }
+        Ok(Self {
+            frames: Arc::new(frames),
+        })
+    }
+
+    // reads the first channel of a WAV file and splits it into `frame_size` long cycles,
+    // files shorter than `frame_size` are treated as a single cycle
+    pub fn from_wav<T: AsRef<std::path::Path>>(
+        path: T,
+        frame_size: usize,
+        max_frames: usize,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let (samples, _spec) = read_first_channel(path)?;
+        let frames = samples
+            .chunks(frame_size.max(1))
+            .filter(|frame| frame.len() == frame_size || samples.len() < frame_size)
+            .take(max_frames)
+            .map(|frame| frame.to_vec())
+            .collect();
+        Self::from_frames(frames)
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    // picks the octave with as many harmonics as fit below nyquist
+    fn mip_level(context: AudioContext, freq: f32) -> usize {
+        let harmonics_needed = (TABLE_SIZE / 2) as f32 * freq.abs() / context.nyquist();
+        (harmonics_needed.log2().ceil().max(0.0) as usize).min(MIP_LEVELS - 1)
+    }
+
+    // `position` morphs between frames, 0.0 is the first frame and 1.0 the last one
+    fn sample(&self, context: AudioContext, phase: f32, freq: f32, position: f32) -> f32 {
+        let level = Self::mip_level(context, freq);
+        let position = position.clamp(0.0, 1.0) * (self.frames.len() - 1) as f32;
+        let index = position.floor() as usize;
+        let next = (index + 1).min(self.frames.len() - 1);
+        let fraction = position - position.floor();
+        let table_position = phase * TABLE_SIZE as f32;
+        let one = read_cycle(&self.frames[index][level], table_position);
+        let other = read_cycle(&self.frames[next][level], table_position);
+        one + (other - one) * fraction
+    }
+
+    pub fn oscillator(
+        &self,
+        context: AudioContext,
+        freq: f32,
+    ) -> WavetableOscillator<std::iter::Repeat<f32>> {
+        self.morphing(context, freq, std::iter::repeat(0.0))
+    }
+
+    pub fn morphing<M: Iterator<Item = f32>>(
+        &self,
+        context: AudioContext,
+        freq: f32,
+        position: M,
+    ) -> WavetableOscillator<M> {
+        WavetableOscillator {
+            context,
+            table: self.clone(),
+            phasor: Phasor::new(),
+            freq,
+            position,
+        }
+    }
+}
+
+impl Instrument for Wavetable {
+    fn voice(&self, context: AudioContext, freq: f32) -> Box<dyn Iterator<Item = f32>> {
+        Box::new(self.oscillator(context, freq))
+    }
+}
+
+pub struct WavetableOscillator<M: Iterator<Item = f32>> {
+    context: AudioContext,
+    table: Wavetable,
+    phasor: Phasor,
+    freq: f32,
+    position: M,
+}
+
+impl<M: Iterator<Item = f32>> Iterator for WavetableOscillator<M> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let position = self.position.next()?;
+        let phase = self.phasor.next_phase(self.context.increment(self.freq)) as f32;
+        let sample = self.table.sample(self.context, phase, self.freq, position);
+        Some(sample * AMPLITUDE)
+    }
+}
+
+#[test]
+fn test_mip_level() {
+    let context = AudioContext::default();
+    assert_eq!(Wavetable::mip_level(context, 10.0), 0);
+    assert_eq!(
+        Wavetable::mip_level(context, context.nyquist() / 2.0),
+        MIP_LEVELS - 1
+    );
+    assert!(Wavetable::mip_level(context, 440.0) < Wavetable::mip_level(context, 880.0));
+    // the same note gets fewer harmonics at a lower sample rate
+    assert!(
+        Wavetable::mip_level(AudioContext::new(22050), 440.0)
+            > Wavetable::mip_level(AudioContext::new(96000), 440.0)
+    );
+}
+
+pub fn run(context: AudioContext) -> Result<(), Box<dyn std::error::Error>> {
+    println!("6 :: generating wavetable sweep");
+    let table = Wavetable::from_wav(crate::lo_pass_filter::INPUT_FILE, TABLE_SIZE, 64)?;
+    println!("loaded {} frames", table.frame_count());
+    let spec = context.wav_spec(1); // mono
+    let mut writer = hound::WavWriter::create(OUTPUT_FILE, spec)?;
+    let length = context.samples(4.0);
+    let sweep = (0..length).map(|index| index as f32 / length as f32);
+    for sample in table.morphing(context, 110.0, sweep) {
+        writer.write_sample(i16::from_synth(sample))?;
+    }
+    for sample in chord(context, &JustIntonation::new(220.0), vec![0, 4, 7], &table).take(length) {
+        writer.write_sample(i16::from_synth(sample / 3.0))?;
+    }
+    Ok(())
+}